@@ -8,6 +8,7 @@
 
 @group(2) @binding(0) var my_texture: texture_2d_array<f32>;
 @group(2) @binding(1) var my_sampler: sampler;
+@group(2) @binding(2) var<uniform> alpha_cutoff: f32;
 
 struct VertexIn {
     @builtin(instance_index) instance_index: u32,
@@ -15,6 +16,7 @@ struct VertexIn {
     @location(1) uv: vec2<f32>,
     @location(2) texture_index: u32,
     @location(3) normal: vec3<f32>,
+    @location(4) ambient_occlusion: f32,
 }
 
 struct VertexOut {
@@ -23,6 +25,7 @@ struct VertexOut {
     @location(1) texture_index: u32,
     @location(2) normal: vec3<f32>,
     @location(3) world_position: vec4<f32>,
+    @location(4) ambient_occlusion: f32,
 }
 
 struct FragIn {
@@ -31,6 +34,7 @@ struct FragIn {
     @location(1) texture_index: u32,
     @location(2) normal: vec3<f32>,
     @location(3) world_position: vec4<f32>,
+    @location(4) ambient_occlusion: f32,
 }
 
 @vertex
@@ -43,13 +47,20 @@ fn vertex(vertex: VertexIn) -> VertexOut {
     output.texture_index = vertex.texture_index;
     output.normal = vertex.normal;
     output.world_position = mesh_position_local_to_world(world_from_local, position);
+    output.ambient_occlusion = vertex.ambient_occlusion;
     return output;
 }
 
 @fragment
 fn fragment(frag: FragIn) -> @location(0) vec4<f32> {
+    // merged faces span several blocks, the texture repeats across them
+    let color = textureSample(my_texture, my_sampler, fract(frag.uv), frag.texture_index);
+    if color.a < alpha_cutoff {
+        discard;
+    }
     var pbr_input: PbrInput = pbr_input_new();
-    pbr_input.material.base_color = textureSample(my_texture, my_sampler, frag.uv, frag.texture_index);
+    // occluded corners keep some light, so that crevices are not pitch black
+    pbr_input.material.base_color = vec4(color.rgb * mix(0.35, 1.0, frag.ambient_occlusion), color.a);
     pbr_input.world_position = frag.world_position;
     pbr_input.frag_coord = frag.clip_position;
     pbr_input.is_orthographic = false;
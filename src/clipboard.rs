@@ -0,0 +1,210 @@
+use bevy::prelude::*;
+
+use crate::{
+    BlockHighligh, GizmosExt, PointedBlock,
+    editing::Selection,
+    spacial::cuboid,
+    swizzle::{Dim3, Swizzle3},
+    terrain::{Block, Modifications, Modify, TerrainBlocks},
+};
+
+pub struct ClipboardPlugin;
+
+/// A cuboid of blocks detached from the terrain
+#[derive(Debug, Clone)]
+pub struct Structure {
+    size: IVec3,
+    /// Blocks ordered by x, then z, then y
+    blocks: Vec<Block>,
+}
+
+/// The last copied structure, and whether it is being positioned for a paste
+#[derive(Resource, Default)]
+pub struct Clipboard {
+    pub structure: Option<Structure>,
+    pub pasting: bool,
+}
+
+impl Plugin for ClipboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Clipboard>().add_systems(
+            Update,
+            (copy_or_cut, paste_transform, paste_apply, paste_preview).chain(),
+        );
+    }
+}
+
+impl Structure {
+    pub fn new(size: IVec3, blocks: Vec<Block>) -> Option<Self> {
+        (blocks.len() == Self::volume(size)?).then_some(Self { size, blocks })
+    }
+    /// How many blocks a structure of a size holds, if it is not empty nor too large
+    fn volume(size: IVec3) -> Option<usize> {
+        if !size.cmpgt(IVec3::ZERO).all() {
+            return None;
+        }
+        // the blocks are indexed with the same arithmetic, it must not overflow either
+        let volume = size.x.checked_mul(size.y)?.checked_mul(size.z)?;
+        Some(volume as usize)
+    }
+    pub fn size(&self) -> IVec3 {
+        self.size
+    }
+    fn index(&self, at: IVec3) -> usize {
+        ((at.y * self.size.z + at.z) * self.size.x + at.x) as usize
+    }
+    pub fn get(&self, at: IVec3) -> Block {
+        self.blocks[self.index(at)]
+    }
+    /// Iterates over all the blocks with their position relative to the lowest corner
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, Block)> + '_ {
+        cuboid(IVec3::ZERO, self.size - IVec3::ONE).map(|at| (at, self.get(at)))
+    }
+
+    /// Copy a region of the terrain, unloaded voxels are copied as air,
+    /// or nothing if the region is too large for a structure
+    pub fn copy(terrain: &TerrainBlocks, min: IVec3, max: IVec3) -> Option<Self> {
+        // a size past the integers wraps around to a negative one, rejected too
+        let size = max.wrapping_sub(min).wrapping_add(IVec3::ONE);
+        let mut structure = Self {
+            size,
+            blocks: vec![Block::AIR; Self::volume(size)?],
+        };
+        for (at, block) in terrain.iter_region(min, max) {
+            let index = structure.index(at - min);
+            structure.blocks[index] = block;
+        }
+        Some(structure)
+    }
+
+    /// Move each block to a new position, `size` being the size of the result
+    ///
+    /// `f` must be a rotation or a reflection of the cuboid, the block states are turned along.
+    fn remap(&self, size: IVec3, f: impl Fn(IVec3) -> IVec3) -> Self {
+        let mut remapped = Self {
+            size,
+            blocks: self.blocks.clone(),
+        };
+        for (at, block) in self.iter() {
+            let index = remapped.index(f(at));
+            // directions are transformed as the difference of two positions
+            let state = block.state.transformed(|dir| f(dir) - f(IVec3::ZERO));
+            remapped.blocks[index] = block.with_state(state);
+        }
+        remapped
+    }
+
+    /// Flip the structure along an axis
+    pub fn mirror(&self, dim: Dim3) -> Self {
+        let size = self.size;
+        self.remap(size, |mut at| {
+            at[dim] = size[dim] - 1 - at[dim];
+            at
+        })
+    }
+
+    /// Quarter turn around the Y axis, as a swap of X and Z followed by a mirror
+    pub fn rotate(&self) -> Self {
+        let swizzle = Swizzle3::ZYX;
+        self.remap(swizzle * self.size, |at| swizzle * at)
+            .mirror(Dim3::X)
+    }
+}
+
+fn copy_or_cut(
+    keys: Res<ButtonInput<KeyCode>>,
+    selection: Res<Selection>,
+    terrain: TerrainBlocks,
+    mut clipboard: ResMut<Clipboard>,
+    mut modifications: ResMut<Modifications>,
+) {
+    if !keys.pressed(KeyCode::ControlLeft) {
+        return;
+    }
+    let cut = keys.just_pressed(KeyCode::KeyX);
+    if let Some((from, to)) = selection.bounds()
+        && (cut || keys.just_pressed(KeyCode::KeyC))
+    {
+        match Structure::copy(&terrain, from, to) {
+            Some(structure) => {
+                clipboard.structure = Some(structure);
+                if cut {
+                    modifications.push(Modify::Fill {
+                        from,
+                        to,
+                        block: Block::AIR,
+                    });
+                }
+            }
+            None => warn!("the selection is too large to be copied"),
+        }
+    }
+    if keys.just_pressed(KeyCode::KeyV) && clipboard.structure.is_some() {
+        clipboard.pasting = !clipboard.pasting;
+    }
+}
+
+fn paste_transform(keys: Res<ButtonInput<KeyCode>>, mut clipboard: ResMut<Clipboard>) {
+    if !clipboard.pasting {
+        return;
+    }
+    let Some(structure) = &clipboard.structure else {
+        return;
+    };
+    let transformed = if keys.just_pressed(KeyCode::KeyR) {
+        structure.rotate()
+    } else if keys.just_pressed(KeyCode::KeyM) {
+        structure.mirror(if keys.pressed(KeyCode::ShiftLeft) {
+            Dim3::Z
+        } else {
+            Dim3::X
+        })
+    } else {
+        return;
+    };
+    clipboard.structure = Some(transformed);
+}
+
+fn paste_apply(
+    keys: Res<ButtonInput<KeyCode>>,
+    pointed: Res<PointedBlock>,
+    mut clipboard: ResMut<Clipboard>,
+    mut modifications: ResMut<Modifications>,
+) {
+    if !clipboard.pasting {
+        return;
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        clipboard.pasting = false;
+    }
+    let (Some(structure), Some((_, anchor))) = (&clipboard.structure, pointed.at) else {
+        return;
+    };
+    if keys.just_pressed(KeyCode::Enter) {
+        for (at, block) in structure.iter() {
+            modifications.push(Modify::Set {
+                at: anchor + at,
+                block,
+            });
+        }
+        clipboard.pasting = false;
+    }
+}
+
+/// Outline where the structure would be pasted
+fn paste_preview(
+    clipboard: Res<Clipboard>,
+    pointed: Res<PointedBlock>,
+    mut gizmos: Gizmos<BlockHighligh>,
+) {
+    if !clipboard.pasting {
+        return;
+    }
+    if let (Some(structure), Some((_, anchor))) = (&clipboard.structure, pointed.at) {
+        gizmos.aabb(
+            anchor.as_vec3(),
+            structure.size.as_vec3(),
+            Color::srgb(0.2, 0.8, 1.0),
+        );
+    }
+}
@@ -55,20 +55,22 @@ fn window_focus(mut events: EventReader<WindowFocused>, mut windows: Query<&mut
 }
 
 pub fn keyboard_input(keys: Res<ButtonInput<KeyCode>>, mut controller: ResMut<ControllerState>) {
+    // the keys held with control are shortcuts, not movements
+    let pressed = |key| !keys.pressed(KeyCode::ControlLeft) && keys.pressed(key);
     let mut dir = Vec3::ZERO;
-    controller.jump = keys.pressed(KeyCode::Space);
-    controller.sneak = keys.pressed(KeyCode::KeyZ);
-    controller.sprint = keys.pressed(KeyCode::KeyA);
-    if keys.pressed(KeyCode::KeyE) {
+    controller.jump = pressed(KeyCode::Space);
+    controller.sneak = pressed(KeyCode::KeyZ);
+    controller.sprint = pressed(KeyCode::KeyA);
+    if pressed(KeyCode::KeyE) {
         dir -= Vec3::Z;
     }
-    if keys.pressed(KeyCode::KeyD) {
+    if pressed(KeyCode::KeyD) {
         dir += Vec3::Z;
     }
-    if keys.pressed(KeyCode::KeyF) {
+    if pressed(KeyCode::KeyF) {
         dir += Vec3::X;
     }
-    if keys.pressed(KeyCode::KeyS) {
+    if pressed(KeyCode::KeyS) {
         dir -= Vec3::X;
     }
     controller.linear_2d = dir.normalize_or_zero();
@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+
+use crate::{
+    BlockHighligh, GizmosExt, PointedBlock,
+    hotbar::Hotbar,
+    terrain::{Modifications, Modify},
+};
+
+pub struct EditingPlugin;
+
+/// A cuboid of blocks picked by its two opposite corners
+#[derive(Resource, Default)]
+pub struct Selection {
+    pub corners: [Option<IVec3>; 2],
+}
+
+impl Plugin for EditingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .add_systems(Update, (select_corners, region_edits, selection_show));
+    }
+}
+
+impl Selection {
+    /// The lowest and highest corners, if both are picked
+    pub fn bounds(&self) -> Option<(IVec3, IVec3)> {
+        let [Some(a), Some(b)] = self.corners else {
+            return None;
+        };
+        Some((a.min(b), a.max(b)))
+    }
+}
+
+fn select_corners(
+    keys: Res<ButtonInput<KeyCode>>,
+    pointed: Res<PointedBlock>,
+    mut selection: ResMut<Selection>,
+) {
+    let Some((at, _)) = pointed.at else {
+        return;
+    };
+    if keys.just_pressed(KeyCode::KeyG) {
+        selection.corners[0] = Some(at);
+    }
+    if keys.just_pressed(KeyCode::KeyH) {
+        selection.corners[1] = Some(at);
+    }
+}
+
+fn region_edits(
+    keys: Res<ButtonInput<KeyCode>>,
+    pointed: Res<PointedBlock>,
+    selection: Res<Selection>,
+    hotbar: Res<Hotbar>,
+    mut modifications: ResMut<Modifications>,
+) {
+    let Some(block) = hotbar.block() else {
+        return;
+    };
+    let shift = keys.pressed(KeyCode::ShiftLeft);
+    if let Some((from, to)) = selection.bounds() {
+        if keys.just_pressed(KeyCode::KeyJ) {
+            modifications.push(if shift {
+                Modify::Hollow { from, to, block }
+            } else {
+                Modify::Fill { from, to, block }
+            });
+        }
+        if keys.just_pressed(KeyCode::KeyK)
+            && let Some(old) = pointed.block
+        {
+            modifications.push(Modify::Replace {
+                from,
+                to,
+                old,
+                new: block,
+            });
+        }
+    }
+    if keys.just_pressed(KeyCode::KeyL)
+        && let Some((at, above)) = pointed.at
+    {
+        modifications.push(if shift {
+            Modify::Cylinder {
+                base: above,
+                radius: 3,
+                height: 6,
+                block,
+            }
+        } else {
+            Modify::Sphere {
+                center: at,
+                radius: 4,
+                block,
+            }
+        });
+    }
+}
+
+fn selection_show(selection: Res<Selection>, mut gizmos: Gizmos<BlockHighligh>) {
+    let color = Color::srgb(1.0, 0.8, 0.2);
+    match selection.bounds() {
+        Some((min, max)) => gizmos.aabb(min.as_vec3(), (max - min + IVec3::ONE).as_vec3(), color),
+        None => {
+            for corner in selection.corners.into_iter().flatten() {
+                gizmos.block(corner, color);
+            }
+        }
+    }
+}
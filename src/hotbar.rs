@@ -0,0 +1,189 @@
+use bevy::{
+    image::{ImageLoaderSettings, ImageSampler},
+    input::mouse::MouseWheel,
+    prelude::*,
+};
+
+use crate::terrain::{Block, BlockRegistry};
+
+pub const HOTBAR_SLOTS: usize = 9;
+
+pub struct HotbarPlugin;
+
+/// The blocks at hand, only the selected one can be placed
+#[derive(Resource, Default)]
+pub struct Hotbar {
+    pub slots: [Option<Block>; HOTBAR_SLOTS],
+    pub selected: usize,
+}
+
+#[derive(Component)]
+struct HotbarSlot(usize);
+
+impl Plugin for HotbarPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Hotbar>()
+            .add_systems(Startup, (fill_slots, setup))
+            .add_systems(
+                Update,
+                (
+                    select_with_keys,
+                    select_with_wheel,
+                    cycle_selected,
+                    slots_show
+                        .after(select_with_keys)
+                        .after(select_with_wheel)
+                        .after(cycle_selected),
+                ),
+            );
+    }
+}
+
+impl Hotbar {
+    pub fn block(&self) -> Option<Block> {
+        self.slots[self.selected]
+    }
+}
+
+/// Put the first placeable blocks of the registry at hand
+fn fill_slots(registry: Res<BlockRegistry>, mut hotbar: ResMut<Hotbar>) {
+    let placeable = registry
+        .iter()
+        .filter(|(_, def)| def.properties.placeable)
+        .map(|(block, _)| block);
+    for (slot, block) in hotbar.slots.iter_mut().zip(placeable) {
+        *slot = Some(block);
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    registry: Res<BlockRegistry>,
+    mut layouts: ResMut<Assets<TextureAtlasLayout>>,
+) {
+    let image = assets.load_with_settings(
+        "textures/blocks.png",
+        |settings: &mut ImageLoaderSettings| settings.sampler = ImageSampler::nearest(),
+    );
+    // the textures are 16x16 tiles stacked vertically
+    let layout = layouts.add(TextureAtlasLayout::from_grid(
+        UVec2::splat(16),
+        1,
+        registry.texture_count() as u32,
+        None,
+        None,
+    ));
+    commands
+        .spawn(Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(10.0),
+            width: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            column_gap: Val::Px(4.0),
+            ..default()
+        })
+        .with_children(|parent| {
+            for slot in 0..HOTBAR_SLOTS {
+                parent.spawn((
+                    Node {
+                        width: Val::Px(40.0),
+                        height: Val::Px(40.0),
+                        border: UiRect::all(Val::Px(3.0)),
+                        padding: UiRect::all(Val::Px(2.0)),
+                        ..default()
+                    },
+                    BorderColor(Color::NONE),
+                    BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.4)),
+                    ImageNode::from_atlas_image(
+                        image.clone(),
+                        TextureAtlas {
+                            layout: layout.clone(),
+                            index: 0,
+                        },
+                    ),
+                    HotbarSlot(slot),
+                ));
+            }
+        });
+}
+
+fn select_with_keys(keys: Res<ButtonInput<KeyCode>>, mut hotbar: ResMut<Hotbar>) {
+    const DIGITS: [KeyCode; HOTBAR_SLOTS] = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    if let Some(slot) = DIGITS.iter().position(|&key| keys.just_pressed(key)) {
+        hotbar.selected = slot;
+    }
+}
+
+fn select_with_wheel(mut wheel: EventReader<MouseWheel>, mut hotbar: ResMut<Hotbar>) {
+    let scroll: f32 = wheel.read().map(|event| event.y).sum();
+    if scroll > 0.0 {
+        hotbar.selected = (hotbar.selected + HOTBAR_SLOTS - 1) % HOTBAR_SLOTS;
+    } else if scroll < 0.0 {
+        hotbar.selected = (hotbar.selected + 1) % HOTBAR_SLOTS;
+    }
+}
+
+/// Swap the block of the selected slot for the next placeable one of the registry
+fn cycle_selected(
+    keys: Res<ButtonInput<KeyCode>>,
+    registry: Res<BlockRegistry>,
+    mut hotbar: ResMut<Hotbar>,
+) {
+    if !keys.just_pressed(KeyCode::Tab) {
+        return;
+    }
+    let placeable: Vec<_> = registry
+        .iter()
+        .filter(|(_, def)| def.properties.placeable)
+        .map(|(block, _)| block)
+        .collect();
+    let current = hotbar.block().and_then(|block| {
+        placeable
+            .iter()
+            .position(|&placeable| placeable.same_type(block))
+    });
+    let next = match (current, keys.pressed(KeyCode::ShiftLeft)) {
+        (None, _) => 0,
+        (Some(index), false) => index + 1,
+        (Some(index), true) => index + placeable.len() - 1,
+    };
+    let selected = hotbar.selected;
+    hotbar.slots[selected] = placeable.get(next % placeable.len().max(1)).copied();
+}
+
+fn slots_show(
+    hotbar: Res<Hotbar>,
+    registry: Res<BlockRegistry>,
+    mut slots: Query<(&HotbarSlot, &mut ImageNode, &mut BorderColor)>,
+) {
+    if !hotbar.is_changed() {
+        return;
+    }
+    for (&HotbarSlot(slot), mut image, mut border) in &mut slots {
+        let icon = hotbar.slots[slot].and_then(|block| registry.icon(block));
+        if let (Some(icon), Some(atlas)) = (icon, &mut image.texture_atlas) {
+            atlas.index = icon as usize;
+        }
+        image.color = if icon.is_some() {
+            Color::WHITE
+        } else {
+            Color::NONE
+        };
+        border.0 = if slot == hotbar.selected {
+            Color::WHITE
+        } else {
+            Color::NONE
+        };
+    }
+}
@@ -1,18 +1,29 @@
+mod clipboard;
 mod controller;
+mod editing;
+mod hotbar;
 mod octahedron;
 mod physics;
 mod ray_travel;
+mod schematic;
 mod spacial;
 mod swizzle;
 mod terrain;
 
 use bevy::{prelude::*, render::view::RenderLayers};
 use bevy_framepace::FramepacePlugin;
+use clipboard::ClipboardPlugin;
 use controller::{ControllerFetch, ControllerPlugin, ControllerState};
+use editing::EditingPlugin;
+use hotbar::{Hotbar, HotbarPlugin};
 use physics::{ApplyPhysics, Collider, Grounded, PhysicsPlugin, Velocity};
 use ray_travel::RayTraveler;
+use schematic::SchematicPlugin;
 use std::{f32::consts::PI, fmt::Write};
-use terrain::{ChunkBlocks, ChunksIndex, Modifications, Modify, TerrainLoader, TerrainPlugin};
+use terrain::{
+    Block, BlockRegistry, Cubic, Cylindrical, MeshStats, Modifications, Modify, Octahedral,
+    Spherical, TerrainBlocks, TerrainLoader, TerrainPlugin, TerrainSchedule,
+};
 
 fn main() {
     App::new()
@@ -22,17 +33,23 @@ fn main() {
             ControllerPlugin,
             FramepacePlugin,
             PhysicsPlugin,
+            HotbarPlugin,
+            EditingPlugin,
+            ClipboardPlugin,
+            SchematicPlugin,
         ))
         .add_systems(Startup, setup)
         .add_systems(
             Update,
             (
                 inspect_ui,
+                schedule_ui,
                 pointed_block,
                 // current_chunk_highlight,
                 pointed_block_show.after(pointed_block),
                 block_place_or_remove.after(pointed_block),
                 player_toggle_flying,
+                player_cycle_loader_metric,
                 player_move_flying.after(ControllerFetch),
                 (player_move_physics, player_rotate)
                     .before(ApplyPhysics)
@@ -64,7 +81,12 @@ fn main() {
                 render_layers: default(),
             },
         )
-        .insert_resource(PointedBlock { at: None })
+        .insert_resource(PointedBlock {
+            at: None,
+            block: None,
+            hit: None,
+            ray: Vec3::NEG_Z,
+        })
         .run();
 }
 
@@ -90,7 +112,10 @@ fn setup(mut commands: Commands) {
     ));
     commands.spawn((
         Player,
-        TerrainLoader::new(128.0, 20.0),
+        // the terrain reaches far around the player, but not much above or below,
+        // and is kept further away for the player to come back,
+        // the blocks reaching three chunks past the mesh for every metric cycled through
+        TerrainLoader::with_radii(PLAYER_LOADER_METRIC, 256.0, 352.0, 448.0, 20.0),
         Camera3d::default(),
         Projection::Perspective(PerspectiveProjection {
             fov: 100.0f32.to_radians(),
@@ -130,6 +155,10 @@ fn setup(mut commands: Commands) {
             (Text("x:".to_string()), font.clone()),
             (Text("y:".to_string()), font.clone()),
             (Text("z:".to_string()), font.clone()),
+            (Text("pointed:".to_string()), font.clone()),
+            (Text("ground:".to_string()), font.clone()),
+            (Text("mesh:".to_string()), font.clone()),
+            (Text("terrain:".to_string()), font.clone()),
         ],
         InspectUi,
     ));
@@ -141,9 +170,14 @@ struct InspectUi;
 fn inspect_ui(
     mut texts: Query<&mut Text>,
     root: Single<(Entity, &Children), With<InspectUi>>,
-    player: Single<&Transform, With<Player>>,
+    player: Single<(&Transform, &Collider), With<Player>>,
+    pointed: Res<PointedBlock>,
+    terrain: TerrainBlocks,
+    registry: Res<BlockRegistry>,
+    stats: Res<MeshStats>,
 ) {
     let (_, children) = root.into_inner();
+    let (player, collider) = *player;
 
     for (axis, child, value) in [
         ("x", 0, player.translation.x),
@@ -154,11 +188,68 @@ fn inspect_ui(
         text.clear();
         write!(text, "{}: {:>+8.3}", axis, value).unwrap();
     }
+
+    // the voxels right below the footprint of the collider
+    let feet = player.translation - collider.anchor - Vec3::Y * 0.5;
+    let ground = terrain
+        .iter_region(
+            feet.floor().as_ivec3(),
+            (feet + collider.size.with_y(0.0)).floor().as_ivec3(),
+        )
+        .map(|(_, block)| block)
+        .find(|&block| registry.is_solid(block));
+
+    for (name, child, block) in [("pointed", 3, pointed.block), ("ground", 4, ground)] {
+        let text = &mut texts.get_mut(children[child]).unwrap().0;
+        text.clear();
+        match block {
+            Some(block) => write!(
+                text,
+                "{}: {}, hardness {}",
+                name,
+                registry.name(block),
+                registry.properties(block).hardness
+            )
+            .unwrap(),
+            None => write!(text, "{}: -", name).unwrap(),
+        }
+    }
+
+    let text = &mut texts.get_mut(children[5]).unwrap().0;
+    text.clear();
+    write!(
+        text,
+        "mesh: {:?}, {:?}, {} vertices, {} indices, {} chunks hidden",
+        stats.config.mode, stats.config.ownership, stats.vertices, stats.indices, stats.hidden
+    )
+    .unwrap();
+}
+
+/// Show the time spent scheduling the terrain during the last frame
+fn schedule_ui(
+    mut texts: Query<&mut Text>,
+    root: Single<&Children, With<InspectUi>>,
+    schedule: Res<TerrainSchedule>,
+) {
+    let times = schedule.times;
+    let text = &mut texts.get_mut(root[6]).unwrap().0;
+    text.clear();
+    write!(
+        text,
+        "terrain: index {:?}, deload {:?}, unload {:?}, detail {:?}, generation {:?}, need mesh {:?}",
+        times.indexer,
+        times.deloader,
+        times.unloader,
+        times.detail,
+        times.generation,
+        times.need_mesh
+    )
+    .unwrap();
 }
 
 trait GizmosExt {
     fn block(&mut self, global: IVec3, color: Color);
-    // fn aabb(&mut self, corner: Vec3, size: Vec3, color: Color);
+    fn aabb(&mut self, corner: Vec3, size: Vec3, color: Color);
 }
 impl<'w, 's, Group: GizmoConfigGroup> GizmosExt for Gizmos<'w, 's, Group> {
     fn block(&mut self, global: IVec3, color: Color) {
@@ -169,16 +260,16 @@ impl<'w, 's, Group: GizmoConfigGroup> GizmosExt for Gizmos<'w, 's, Group> {
         );
     }
 
-    // fn aabb(&mut self, corner: Vec3, size: Vec3, color: Color) {
-    //     self.cuboid(
-    //         Transform {
-    //             translation: corner + 0.5 * size,
-    //             rotation: default(),
-    //             scale: size,
-    //         },
-    //         color,
-    //     );
-    // }
+    fn aabb(&mut self, corner: Vec3, size: Vec3, color: Color) {
+        self.cuboid(
+            Transform {
+                translation: corner + 0.5 * size,
+                rotation: default(),
+                scale: size,
+            },
+            color,
+        );
+    }
 }
 
 fn axis_overlay(mut gizmos: Gizmos<AxisOverlay>, transform: Single<&Transform, With<Player>>) {
@@ -254,13 +345,20 @@ fn current_chunk_highlight(player: Single<&Transform, With<Player>>, mut gizmos:
 fn block_place_or_remove(
     button: Res<ButtonInput<MouseButton>>,
     pointed: Res<PointedBlock>,
+    hotbar: Res<Hotbar>,
+    registry: Res<BlockRegistry>,
     mut modifications: ResMut<Modifications>,
 ) {
     if let Some((at, from)) = pointed.at {
         if button.just_pressed(MouseButton::Left) {
             modifications.push(Modify::Remove { at });
-        } else if button.just_pressed(MouseButton::Right) {
-            modifications.push(Modify::Place { at: from });
+        } else if button.just_pressed(MouseButton::Right)
+            && let (Some(block), Some((dir, point))) = (hotbar.block(), pointed.hit)
+        {
+            modifications.push(Modify::Place {
+                at: from,
+                block: registry.placed(block, dir, point, pointed.ray),
+            });
         }
     }
 }
@@ -268,33 +366,69 @@ fn block_place_or_remove(
 #[derive(Resource)]
 struct PointedBlock {
     at: Option<(IVec3, IVec3)>,
+    block: Option<Block>,
+    /// The direction in which the ray entered the pointed block, and where
+    hit: Option<(IVec3, Vec3)>,
+    ray: Vec3,
 }
 
-fn pointed_block_show(pointed: Res<PointedBlock>, mut gizmos: Gizmos<BlockHighligh>) {
-    if let Some((at, _)) = pointed.at {
-        gizmos.block(at, Color::BLACK);
+/// Outline the shape of the pointed block
+fn pointed_block_show(
+    pointed: Res<PointedBlock>,
+    terrain: TerrainBlocks,
+    registry: Res<BlockRegistry>,
+    mut gizmos: Gizmos<BlockHighligh>,
+) {
+    if let (Some((at, _)), Some(block)) = (pointed.at, pointed.block) {
+        for part in registry.outline(block, |side| terrain.get(at + side.dir())) {
+            let part = part.translated(at.as_vec3());
+            gizmos.aabb(part.min - 1e-4, part.max - part.min + 2e-4, Color::BLACK);
+        }
     }
 }
 
 fn pointed_block(
     player: Single<&Transform, With<Player>>,
-    blocks: Query<&ChunkBlocks>,
-    terrain: Res<ChunksIndex>,
+    terrain: TerrainBlocks,
+    registry: Res<BlockRegistry>,
     mut pointed: ResMut<PointedBlock>,
 ) {
     let ray = player.rotation * -Dir3::Z;
     let traveler = RayTraveler::new(player.translation, ray, 16.0);
+    pointed.ray = ray.into();
     for step in traveler {
-        if let Some((chunk, local)) = terrain.global_to_local(step.to) {
-            if let Ok(blocks) = blocks.get(chunk) {
-                if blocks.get(local) {
-                    pointed.at = Some((step.to, step.from));
-                    return;
-                }
-            }
+        if let Some(block) = terrain.get(step.to)
+            && registry.is_targetable(block)
+        {
+            pointed.at = Some((step.to, step.from));
+            pointed.block = Some(block);
+            pointed.hit = Some((step.dir, step.at));
+            return;
         }
     }
     pointed.at = None;
+    pointed.block = None;
+    pointed.hit = None;
+}
+
+/// A cylinder as wide as the player mesh zone and half as high
+const PLAYER_LOADER_METRIC: Cylindrical = Cylindrical::new(256.0, 128.0);
+
+/// Switch the metric the player loads the terrain with, to compare them
+fn player_cycle_loader_metric(
+    mut loader: Single<&mut TerrainLoader, With<Player>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut current: Local<usize>,
+) {
+    if keys.just_pressed(KeyCode::KeyP) {
+        *current = (*current + 1) % 4;
+        match *current {
+            0 => loader.set_metric(PLAYER_LOADER_METRIC),
+            1 => loader.set_metric(Octahedral),
+            2 => loader.set_metric(Spherical),
+            _ => loader.set_metric(Cubic),
+        }
+    }
 }
 
 fn player_toggle_flying(
@@ -303,7 +437,8 @@ fn player_toggle_flying(
     keys: Res<ButtonInput<KeyCode>>,
 ) {
     let (player, physics) = *player;
-    if keys.just_pressed(KeyCode::KeyV) {
+    // control and V toggles the paste instead
+    if keys.just_pressed(KeyCode::KeyV) && !keys.pressed(KeyCode::ControlLeft) {
         if physics {
             commands.entity(player).remove::<Velocity>();
         } else {
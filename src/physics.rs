@@ -4,7 +4,7 @@ use crate::{
     // GizmosExt,
     ray_travel::RayTraveler,
     swizzle::{Dim3, Dim3Selector},
-    terrain::{ChunkBlocks, ChunksIndex},
+    terrain::{Block, BlockBox, BlockRegistry, TerrainBlocks},
 };
 
 #[derive(SystemSet, Clone, PartialEq, Eq, Debug, Hash)]
@@ -52,8 +52,8 @@ fn apply_gravity(velocity: Query<&mut Velocity>, time: Res<Time>) {
 
 fn apply_velocity(
     // mut gizmos: Gizmos,
-    chunks: Res<ChunksIndex>,
-    blocks: Query<&ChunkBlocks>,
+    terrain: TerrainBlocks,
+    registry: Res<BlockRegistry>,
     collider: Query<(Entity, &mut Transform, &Collider, &mut Velocity)>,
     time: Res<Time>,
     mut commands: Commands,
@@ -85,9 +85,18 @@ fn apply_velocity(
 
         let mut grounded = false;
 
-        'search: while let Ok(dir) = shift.try_into() {
+        'search: while let Ok(dir) = Dir3::try_from(shift) {
             let length = shift.norm();
-            for step in RayTraveler::new(corner_active, dir, length) {
+            // partial blocks can be hit within the layers of voxels the collider already is in,
+            // before the ones it enters
+            let current = [Dim3::X, Dim3::Y, Dim3::Z]
+                .into_iter()
+                .filter(|&dim| dir[dim] != 0.0)
+                .map(|dim| {
+                    let layer = corner_active[dim].floor() as i32;
+                    (dim, layer, corner_active[dim], corner_active, 0.0)
+                });
+            let entered = RayTraveler::new(corner_active, dir, length).map(|step| {
                 // to avoid code duplication, each symetric situation through dimension permutation is made identic by a reversible swizzle
                 let dim = match step.dir {
                     IVec3::X | IVec3::NEG_X => Dim3::X,
@@ -95,33 +104,65 @@ fn apply_velocity(
                     IVec3::Z | IVec3::NEG_Z => Dim3::Z,
                     _ => unreachable!(),
                 };
-
-                let (_, [plane_u, plane_v]) = (step.at - corner_select).split(dim);
+                // the side of the collider is exactly on the boundary of the layer it enters
+                let layer = step.to[dim];
+                let boundary = if dir[dim] > 0.0 { layer } else { layer + 1 };
+                (dim, layer, boundary as f32, step.at, step.time)
+            });
+            // `reached` is how far the side of the collider is along the dimension at `time`
+            for (dim, layer, reached, at, time) in current.chain(entered) {
+                let (_, [plane_u, plane_v]) = (at - corner_select).split(dim);
                 let (_, [size_u, size_v]) = cl.size.split(dim);
 
                 // on the UV plane, we select all voxels covered by the side of the collider
-                for u in plane_u.floor() as i32..=(plane_u + size_u).floor() as i32 {
-                    for v in plane_v.floor() as i32..=(plane_v + size_v).floor() as i32 {
-                        // we find the global coordinate of each voxel
-                        let selected = IVec3::compose(dim, step.to[dim], [u, v]);
-
-                        // if a block is present, a collision occur
-                        if chunks.get(blocks, selected) {
-                            // we correct the vector component to stop at the collision
-                            shift[dim] *= step.time / length;
-                            // we stop slightly before the collision
-                            shift[dim] -= dir[dim].signum() * 1e-4;
-                            // the collision absorbs all kinetic energy
-                            vl.linear[dim] = 0.0;
-
-                            if step.dir == IVec3::NEG_Y {
-                                grounded = true;
-                            }
-
-                            // we restart the collision search with the corrected shift
-                            continue 'search;
-                        }
+                let covered = (plane_u.floor() as i32..=(plane_u + size_u).floor() as i32)
+                    .flat_map(|u| {
+                        (plane_v.floor() as i32..=(plane_v + size_v).floor() as i32)
+                            .map(move |v| [u, v])
+                    })
+                    // we find the global coordinate of each voxel
+                    .map(|uv| IVec3::compose(dim, layer, uv))
+                    .collect::<Vec<_>>();
+
+                // the earliest time the side of the collider meets a solid box
+                let contact = covered
+                    .iter()
+                    .zip(terrain.get_many(covered.iter().copied()))
+                    .flat_map(|(&voxel, block)| solid_boxes(&terrain, &registry, voxel, block))
+                    .filter(|part| {
+                        let (_, [min_u, min_v]) = part.min.split(dim);
+                        let (_, [max_u, max_v]) = part.max.split(dim);
+                        min_u < plane_u + size_u
+                            && max_u > plane_u
+                            && min_v < plane_v + size_v
+                            && max_v > plane_v
+                    })
+                    .filter_map(|part| {
+                        // how far the box is ahead along the dimension, boxes behind are ignored
+                        let gap = if dir[dim] > 0.0 {
+                            part.min[dim] - reached
+                        } else {
+                            reached - part.max[dim]
+                        };
+                        (gap >= 0.0).then_some(time + gap / dir[dim].abs())
+                    })
+                    .filter(|&contact| contact <= length)
+                    .min_by(f32::total_cmp);
+
+                if let Some(contact) = contact {
+                    // we correct the vector component to stop at the collision
+                    shift[dim] *= contact / length;
+                    // we stop slightly before the collision
+                    shift[dim] -= dir[dim].signum() * 1e-4;
+                    // the collision absorbs all kinetic energy
+                    vl.linear[dim] = 0.0;
+
+                    if dim == Dim3::Y && dir.y < 0.0 {
+                        grounded = true;
                     }
+
+                    // we restart the collision search with the corrected shift
+                    continue 'search;
                 }
             }
             // no more collisions are detected
@@ -145,7 +186,11 @@ fn apply_velocity(
             commands.entity(entity).remove::<Grounded>();
         }
 
-        if chunks.get(blocks, (corner_active + shift).floor().as_ivec3()) {
+        let corner = corner_active + shift;
+        let voxel = corner.floor().as_ivec3();
+        if solid_boxes(&terrain, &registry, voxel, terrain.get(voxel))
+            .any(|part| part.min.cmplt(corner).all() && part.max.cmpgt(corner).all())
+        {
             println!("collider tunneling");
             println!(" - pos    {:.10}", corner_active);
             println!(" - shift* {:.10}", shift);
@@ -158,3 +203,19 @@ fn apply_velocity(
         tr.translation += shift;
     }
 }
+
+/// The boxes of the block at a voxel colliders are stopped by, in global coordinates
+fn solid_boxes(
+    terrain: &TerrainBlocks,
+    registry: &BlockRegistry,
+    voxel: IVec3,
+    block: Option<Block>,
+) -> impl Iterator<Item = BlockBox> {
+    let boxes = block
+        .filter(|&block| registry.is_solid(block))
+        .map(|block| registry.boxes(block, |side| terrain.get(voxel + side.dir())))
+        .unwrap_or_default();
+    boxes
+        .into_iter()
+        .map(move |part| part.translated(voxel.as_vec3()))
+}
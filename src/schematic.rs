@@ -0,0 +1,188 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::Path,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clipboard::{Clipboard, Structure},
+    editing::Selection,
+    terrain::{BlockRegistry, BlockState, TerrainBlocks},
+};
+
+const VERSION: u32 = 2;
+/// Where the selection is exported, and where imports are read from
+const SCHEMATIC_PATH: &str = "schematics/clipboard.json";
+
+pub struct SchematicPlugin;
+
+/// On-disk representation of a structure
+///
+/// Blocks are referenced by name through a palette, so that the file
+/// does not depend on the numbering of blocks in a given build.
+#[derive(Serialize, Deserialize)]
+struct Schematic {
+    version: u32,
+    size: [i32; 3],
+    palette: Vec<PaletteEntry>,
+    /// Palette indices, ordered by x, then z, then y
+    blocks: Vec<u16>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PaletteEntry {
+    name: String,
+    /// Encoded `BlockState`
+    state: u8,
+}
+
+#[derive(Debug)]
+pub enum SchematicError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+    UnknownBlock(String),
+    InvalidState(u8),
+    /// The dimensions, palette and voxel data do not agree
+    Inconsistent,
+}
+
+impl Plugin for SchematicPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (export_selection, import_to_clipboard));
+    }
+}
+
+impl fmt::Display for SchematicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Format(err) => write!(f, "malformed schematic: {err}"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported schematic version {version}")
+            }
+            Self::UnknownBlock(name) => write!(f, "unknown block {name:?}"),
+            Self::InvalidState(bits) => write!(f, "invalid block state {bits}"),
+            Self::Inconsistent => write!(f, "inconsistent schematic data"),
+        }
+    }
+}
+
+impl std::error::Error for SchematicError {}
+
+impl From<io::Error> for SchematicError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SchematicError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Format(err)
+    }
+}
+
+pub fn save(
+    structure: &Structure,
+    registry: &BlockRegistry,
+    path: &Path,
+) -> Result<(), SchematicError> {
+    let mut palette = Vec::new();
+    let blocks = structure
+        .iter()
+        .map(|(_, block)| {
+            let index = palette.iter().position(|&entry| entry == block);
+            index.unwrap_or_else(|| {
+                palette.push(block);
+                palette.len() - 1
+            }) as u16
+        })
+        .collect();
+    let schematic = Schematic {
+        version: VERSION,
+        size: structure.size().to_array(),
+        palette: palette
+            .iter()
+            .map(|&block| PaletteEntry {
+                name: registry.name(block).to_string(),
+                state: block.state.to_bits(),
+            })
+            .collect(),
+        blocks,
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    serde_json::to_writer(BufWriter::new(File::create(path)?), &schematic)?;
+    Ok(())
+}
+
+pub fn load(registry: &BlockRegistry, path: &Path) -> Result<Structure, SchematicError> {
+    let schematic: Schematic = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    if schematic.version != VERSION {
+        return Err(SchematicError::UnsupportedVersion(schematic.version));
+    }
+    let palette = schematic
+        .palette
+        .into_iter()
+        .map(|entry| {
+            let block = registry
+                .find(&entry.name)
+                .ok_or(SchematicError::UnknownBlock(entry.name))?;
+            let state = BlockState::from_bits(entry.state)
+                .ok_or(SchematicError::InvalidState(entry.state))?;
+            Ok::<_, SchematicError>(block.with_state(state))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let blocks = schematic
+        .blocks
+        .into_iter()
+        .map(|index| palette.get(index as usize).copied())
+        .collect::<Option<Vec<_>>>()
+        .ok_or(SchematicError::Inconsistent)?;
+    Structure::new(IVec3::from_array(schematic.size), blocks).ok_or(SchematicError::Inconsistent)
+}
+
+fn export_selection(
+    keys: Res<ButtonInput<KeyCode>>,
+    selection: Res<Selection>,
+    terrain: TerrainBlocks,
+    registry: Res<BlockRegistry>,
+) {
+    if !(keys.pressed(KeyCode::ControlLeft) && keys.just_pressed(KeyCode::KeyE)) {
+        return;
+    }
+    let Some((min, max)) = selection.bounds() else {
+        return;
+    };
+    let Some(structure) = Structure::copy(&terrain, min, max) else {
+        warn!("the selection is too large to be exported");
+        return;
+    };
+    match save(&structure, &registry, Path::new(SCHEMATIC_PATH)) {
+        Ok(()) => info!("selection exported to {SCHEMATIC_PATH}"),
+        Err(err) => warn!("failed to export {SCHEMATIC_PATH}: {err}"),
+    }
+}
+
+/// Load the schematic in the clipboard, ready to be pasted
+fn import_to_clipboard(
+    keys: Res<ButtonInput<KeyCode>>,
+    registry: Res<BlockRegistry>,
+    mut clipboard: ResMut<Clipboard>,
+) {
+    if !(keys.pressed(KeyCode::ControlLeft) && keys.just_pressed(KeyCode::KeyO)) {
+        return;
+    }
+    match load(&registry, Path::new(SCHEMATIC_PATH)) {
+        Ok(structure) => {
+            clipboard.structure = Some(structure);
+            clipboard.pasting = true;
+        }
+        Err(err) => warn!("failed to import {SCHEMATIC_PATH}: {err}"),
+    }
+}
@@ -1,8 +1,8 @@
-use std::ops::Index;
+use std::ops::{Index, IndexMut};
 
 use bevy::math::IVec3;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Side {
     XPos,
     XNeg,
@@ -20,7 +20,28 @@ impl Side {
         Self::ZPos,
         Self::ZNeg,
     ];
+
+    pub fn dir(self) -> IVec3 {
+        Sides::AXIS[self]
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::XPos => Self::XNeg,
+            Self::XNeg => Self::XPos,
+            Self::YPos => Self::YNeg,
+            Self::YNeg => Self::YPos,
+            Self::ZPos => Self::ZNeg,
+            Self::ZNeg => Self::ZPos,
+        }
+    }
+
+    /// The side a unit axis vector points to
+    pub fn from_dir(dir: IVec3) -> Option<Self> {
+        Self::ALL.into_iter().find(|side| side.dir() == dir)
+    }
 }
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Sides<T> {
     pub x_pos: T,
     pub x_neg: T,
@@ -40,6 +61,16 @@ impl Sides<IVec3> {
     };
 }
 impl<T> Sides<T> {
+    pub fn from_fn(mut f: impl FnMut(Side) -> T) -> Self {
+        Sides {
+            x_pos: f(Side::XPos),
+            x_neg: f(Side::XNeg),
+            y_pos: f(Side::YPos),
+            y_neg: f(Side::YNeg),
+            z_pos: f(Side::ZPos),
+            z_neg: f(Side::ZNeg),
+        }
+    }
     pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> Sides<U> {
         Sides {
             x_pos: f(self.x_pos),
@@ -65,6 +96,18 @@ impl<T> Index<Side> for Sides<T> {
         }
     }
 }
+impl<T> IndexMut<Side> for Sides<T> {
+    fn index_mut(&mut self, index: Side) -> &mut Self::Output {
+        match index {
+            Side::XPos => &mut self.x_pos,
+            Side::XNeg => &mut self.x_neg,
+            Side::YPos => &mut self.y_pos,
+            Side::YNeg => &mut self.y_neg,
+            Side::ZPos => &mut self.z_pos,
+            Side::ZNeg => &mut self.z_neg,
+        }
+    }
+}
 impl<T> From<Sides<T>> for [T; 6] {
     fn from(
         Sides {
@@ -112,63 +155,82 @@ impl<'a, T> IntoIterator for &'a Sides<T> {
     }
 }
 
+/// Which chunks around a centre one a `Neighborhood` holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighborhoodSize {
+    /// The centre and the 3 chunks sharing a face with it toward positive coordinates
+    Positive,
+    /// The centre and the 6 chunks sharing a face with it
+    Faces,
+    /// The whole 3×3×3 cube, edges and corners included
+    Full,
+}
+impl NeighborhoodSize {
+    pub fn contains(self, offset: IVec3) -> bool {
+        match self {
+            Self::Positive => offset.min_element() >= 0 && offset.element_sum() <= 1,
+            Self::Faces => offset.abs().element_sum() <= 1,
+            Self::Full => offset.abs().max_element() <= 1,
+        }
+    }
+}
+
+/// A value for a chunk and for some of the chunks around it, addressed by their offset
+#[derive(Debug, Clone)]
 pub struct Neighborhood<T> {
-    pub zero: T,
-    pub x_pos: T,
-    pub x_neg: T,
-    pub y_pos: T,
-    pub y_neg: T,
-    pub z_pos: T,
-    pub z_neg: T,
+    /// Ordered by x, then y, then z offset, as given by `cuboid`
+    items: [Option<T>; 27],
 }
-impl From<IVec3> for Neighborhood<IVec3> {
-    fn from(value: IVec3) -> Self {
+impl Neighborhood<IVec3> {
+    /// The coordinates of the chunks around `centre`
+    pub fn new(centre: IVec3, size: NeighborhoodSize) -> Self {
         Self {
-            zero: value,
-            x_pos: value + IVec3::X,
-            x_neg: value - IVec3::X,
-            y_pos: value + IVec3::Y,
-            y_neg: value - IVec3::Y,
-            z_pos: value + IVec3::Z,
-            z_neg: value - IVec3::Z,
+            items: std::array::from_fn(|index| {
+                let offset = neighborhood_offset(index);
+                size.contains(offset).then_some(centre + offset)
+            }),
         }
     }
 }
 impl<T> Neighborhood<T> {
-    // fn map<U>(self, mut f: impl FnMut(T) -> U) -> Neighborhood<U> {
-    //     Neighborhood {
-    //         zero: f(self.zero),
-    //         x_pos: f(self.x_pos),
-    //         x_neg: f(self.x_neg),
-    //         y_pos: f(self.y_pos),
-    //         y_neg: f(self.y_neg),
-    //         z_pos: f(self.z_pos),
-    //         z_neg: f(self.z_neg),
-    //     }
-    // }
-    pub fn as_array(&self) -> [&T; 7] {
-        [
-            &self.zero,
-            &self.x_pos,
-            &self.x_neg,
-            &self.y_pos,
-            &self.y_neg,
-            &self.z_pos,
-            &self.z_neg,
-        ]
+    pub fn centre(&self) -> &T {
+        self.items[13].as_ref().unwrap()
+    }
+    /// The value of the chunk at an offset from the centre, if the neighbourhood holds it
+    pub fn get(&self, offset: IVec3) -> Option<&T> {
+        if offset.abs().max_element() > 1 {
+            return None;
+        }
+        let [x, y, z] = (offset + IVec3::ONE).to_array();
+        self.items[(x * 9 + y * 3 + z) as usize].as_ref()
     }
     pub fn all(&self, f: impl FnMut(&T) -> bool) -> bool {
-        self.as_array().into_iter().all(f)
+        self.items.iter().flatten().all(f)
+    }
+    /// Keep the values `f` gives, leaving out the others
+    pub fn filter_map<U>(self, mut f: impl FnMut(T) -> Option<U>) -> Neighborhood<U> {
+        Neighborhood {
+            items: self.items.map(|item| item.and_then(&mut f)),
+        }
     }
     pub fn try_map<U>(self, mut f: impl FnMut(T) -> Option<U>) -> Option<Neighborhood<U>> {
-        Some(Neighborhood {
-            zero: f(self.zero)?,
-            x_pos: f(self.x_pos)?,
-            x_neg: f(self.x_neg)?,
-            y_pos: f(self.y_pos)?,
-            y_neg: f(self.y_neg)?,
-            z_pos: f(self.z_pos)?,
-            z_neg: f(self.z_neg)?,
-        })
+        let mut items = std::array::from_fn(|_| None);
+        for (mapped, item) in items.iter_mut().zip(self.items) {
+            if let Some(item) = item {
+                *mapped = Some(f(item)?);
+            }
+        }
+        Some(Neighborhood { items })
     }
 }
+fn neighborhood_offset(index: usize) -> IVec3 {
+    let index = index as i32;
+    IVec3::new(index / 9, index / 3 % 3, index % 3) - IVec3::ONE
+}
+
+/// Iterates over all the coordinates of the cuboid between `min` and `max` (inclusive)
+pub fn cuboid(min: IVec3, max: IVec3) -> impl Iterator<Item = IVec3> {
+    (min.x..=max.x).flat_map(move |x| {
+        (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3 { x, y, z }))
+    })
+}
@@ -2,7 +2,7 @@ use std::ops::{Index, IndexMut};
 
 use bevy::math::{Dir3, IVec3, Vec3, Vec3Swizzles};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Dim3 {
     X,
     Y,
@@ -10,7 +10,7 @@ pub enum Dim3 {
 }
 
 #[derive(Debug, Clone, Copy)]
-#[allow(dead_code)]
+#[allow(dead_code, clippy::upper_case_acronyms)]
 pub enum Swizzle3 {
     XYZ,
     XZY,
@@ -1,31 +1,109 @@
 mod generation;
+mod history;
+mod journal;
+mod metric;
+mod persistence;
+mod registry;
 mod render;
+mod shape;
+mod state;
+mod storage;
+mod visibility;
+
+pub use metric::{Cubic, Cylindrical, DistanceMetric, Octahedral, Spherical};
+pub use registry::BlockRegistry;
+pub use render::{MeshStats, MeshingConfig};
+pub use shape::BlockBox;
+pub use state::BlockState;
 
 use crate::{
-    spacial::{Neighborhood, Sides},
+    spacial::{Neighborhood, cuboid},
     terrain::{
         generation::TerrainGenerator,
-        render::{TerrainMaterial, chunk_meshing, setup_render},
+        history::{Edit, History, redo, undo},
+        persistence::{AUTOSAVE_PERIOD, WorldSave, save_world},
+        render::{
+            Detail, FaceOwnership, Meshing, MeshingMode, TerrainMaterial, chunk_mesh_upload,
+            chunk_meshing, mesh_stats, setup_render,
+        },
+        storage::BlockStorage,
+        visibility::{FaceConnections, visibility_culling},
     },
 };
 
-use super::octahedron;
 use bevy::{
-    input::common_conditions::input_just_pressed,
-    platform::collections::{HashMap, hash_map::Entry},
+    ecs::system::SystemParam,
+    input::common_conditions::{input_just_pressed, input_pressed},
+    platform::{
+        collections::{HashMap, HashSet, hash_map::Entry},
+        time::Instant,
+    },
     prelude::*,
+    tasks::{AsyncComputeTaskPool, Task, futures::check_ready},
+    time::common_conditions::on_timer,
 };
-use std::ops::RangeInclusive;
+use std::{sync::Arc, time::Duration};
 
 pub const CHUNK_WIDTH: i32 = 32;
+/// How many chunks can be generated at the same time
+const GENERATION_JOBS: usize = 16;
 
 pub struct TerrainPlugin;
 
 /// An entity that causes the terrain to be loaded around it
-#[derive(Component, Clone, Copy)]
+#[derive(Component, Clone)]
 pub struct TerrainLoader {
-    radius: f32,
+    /// Chunks closer than this are meshed
+    mesh: f32,
+    /// Chunks closer than this get their blocks
+    blocks: f32,
+    /// Chunks further than this are unloaded
+    retain: f32,
+    /// How much further than its radius a chunk has to be to leave a zone
     buffer: f32,
+    metric: Arc<dyn DistanceMetric>,
+    /// The chunk the loader was last seen in, the terrain around it being scheduled
+    chunk: Option<IVec3>,
+}
+
+/// A loader entered another chunk, or has to schedule the terrain around it again
+#[derive(Event, Debug, Clone, Copy)]
+pub struct LoaderMoved {
+    pub loader: Entity,
+    pub from: Option<IVec3>,
+    pub to: IVec3,
+}
+
+/// The chunks the terrain systems still have work for, collected when a loader moves
+/// so that nothing is scanned while the loaders stay within their chunks
+#[derive(Resource, Default)]
+pub struct TerrainSchedule {
+    /// Chunks inside the blocks zone waiting to be generated, the nearest last
+    generation: Vec<Entity>,
+    /// Chunks inside the mesh zone without a mesh, waiting on the blocks of their neighbours
+    mesh: HashSet<IVec3>,
+    /// Whether the chunks waiting for a mesh are to be checked again
+    check_mesh: bool,
+    /// Chunks within reach of each loader, to know which ones it leaves when it moves
+    reached: HashMap<Entity, HashSet<IVec3>>,
+    /// Chunks a loader moved away from, losing their mesh unless another loader holds them
+    deload: HashSet<IVec3>,
+    /// Chunks a loader moved out of reach of, unloaded unless another loader holds them
+    unload: HashSet<IVec3>,
+    /// Chunks around a loader that moved, their detail changing with its distance
+    detail: HashSet<IVec3>,
+    pub times: ScheduleTimes,
+}
+
+/// Time spent during the last frame by each system scheduling the terrain
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScheduleTimes {
+    pub indexer: Duration,
+    pub deloader: Duration,
+    pub unloader: Duration,
+    pub detail: Duration,
+    pub generation: Duration,
+    pub need_mesh: Duration,
 }
 
 #[derive(Component)]
@@ -33,33 +111,122 @@ struct Chunk {
     chunk: IVec3,
 }
 
+/// A chunk got its blocks, generated then with its saved edits applied
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ChunkGenerated {
+    pub chunk: IVec3,
+    pub entity: Entity,
+}
+
+/// A chunk got a new mesh, or new meshes when it has translucent faces
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ChunkMeshed {
+    pub chunk: IVec3,
+    pub entity: Entity,
+}
+
+/// A chunk was forgotten along with its entity, its edits staying in the save
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ChunkUnloaded {
+    pub chunk: IVec3,
+}
+
+/// A block of the loaded terrain was replaced
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BlockChanged {
+    pub at: IVec3,
+    pub before: Block,
+    pub after: Block,
+}
+
 #[derive(Resource)]
 pub struct ChunksIndex {
     chunks: HashMap<IVec3, Entity>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Block {
-    Air,
-    Grass,
-    Stone,
-    Dirt,
-    Sand,
+/// Read access to the blocks of the loaded terrain, addressed by global coordinates
+#[derive(SystemParam)]
+pub struct TerrainBlocks<'w, 's> {
+    index: Res<'w, ChunksIndex>,
+    chunks: Query<'w, 's, &'static ChunkBlocks>,
+}
+
+/// Write access to the blocks of the loaded terrain, keeping meshes and saves up to date
+///
+/// Chunks needing a new mesh are collected, and only marked once `finish` is called.
+#[derive(SystemParam)]
+struct TerrainEditor<'w, 's> {
+    index: Res<'w, ChunksIndex>,
+    chunks: Query<'w, 's, &'static mut ChunkBlocks>,
+    save: ResMut<'w, WorldSave>,
+    commands: Commands<'w, 's>,
+    config: Res<'w, MeshingConfig>,
+    changes: EventWriter<'w, BlockChanged>,
+    reload: Local<'s, HashSet<Entity>>,
+}
+
+/// A block type, described by its entry in the `BlockRegistry`, along with its state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Block {
+    id: u16,
+    pub state: BlockState,
 }
 
 /// Store terrain generation parameters
 #[derive(Resource)]
 struct Terrain;
 
-#[derive(Component)]
+#[derive(Component, Default, Clone)]
 pub struct ChunkBlocks {
-    blocks: HashMap<IVec3, Block>,
+    blocks: BlockStorage,
 }
 
+/// An edit of the terrain, regions are inclusive and their corners can be given in any order
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Modify {
-    Remove { at: IVec3 },
-    Place { at: IVec3 },
+    Remove {
+        at: IVec3,
+    },
+    Place {
+        at: IVec3,
+        block: Block,
+    },
+    /// Overwrite a voxel whatever it holds
+    Set {
+        at: IVec3,
+        block: Block,
+    },
+    /// Set every voxel of a cuboid
+    Fill {
+        from: IVec3,
+        to: IVec3,
+        block: Block,
+    },
+    /// Swap a block type for another inside a cuboid
+    Replace {
+        from: IVec3,
+        to: IVec3,
+        old: Block,
+        new: Block,
+    },
+    /// Set the walls of a cuboid and empty its inside
+    Hollow {
+        from: IVec3,
+        to: IVec3,
+        block: Block,
+    },
+    Sphere {
+        center: IVec3,
+        radius: i32,
+        block: Block,
+    },
+    /// A vertical cylinder standing on `base`
+    Cylinder {
+        base: IVec3,
+        radius: i32,
+        height: i32,
+        block: Block,
+    },
 }
 
 #[derive(Resource)]
@@ -67,10 +234,23 @@ pub struct Modifications {
     queue: Vec<Modify>,
 }
 
+/// The chunk needs a new mesh, built ahead of the others if `urgent`
 #[derive(Component)]
-struct MeshReload;
+struct MeshReload {
+    urgent: bool,
+}
+
+/// Chunks with a mesh or about to get one
+type MeshedOrMeshing = Or<(With<Mesh3d>, With<Meshing>)>;
+/// Chunks without a mesh and not waiting for one
+type Unmeshed = (Without<Mesh3d>, Without<MeshReload>, Without<Meshing>);
+
+/// The blocks of a chunk being generated on the task pool, removing it cancels the job
+#[derive(Component)]
+struct Generating(Task<(ChunkBlocks, FaceConnections)>);
 
 impl Modifications {
+    /// Modifications pushed during the same frame are undone together
     pub fn push(&mut self, modify: Modify) {
         self.queue.push(modify);
     }
@@ -78,24 +258,77 @@ impl Modifications {
 
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
+        let registry = BlockRegistry::load_from_file();
         app.add_plugins(MaterialPlugin::<TerrainMaterial>::default())
             .add_systems(Startup, setup_render)
             .add_systems(
                 Update,
                 (
-                    chunk_indexer,
-                    chunk_deloader,
-                    chunk_generation.before(chunk_meshing),
-                    chunk_need_mesh.before(chunk_meshing),
-                    apply_modifications.before(chunk_meshing),
+                    track_loaders,
+                    (
+                        chunk_indexer,
+                        (chunk_deloader, chunk_unloader, chunk_detail),
+                    )
+                        .chain()
+                        .after(track_loaders)
+                        .before(chunk_meshing)
+                        .run_if(on_event::<LoaderMoved>),
+                    chunk_generation.after(chunk_indexer),
+                    chunk_generated.before(chunk_meshing),
+                    collect_mesh_candidates
+                        .after(chunk_indexer)
+                        .run_if(on_event::<LoaderMoved>),
+                    chunk_need_mesh
+                        .after(collect_mesh_candidates)
+                        .after(chunk_generated)
+                        .before(chunk_meshing),
+                    (
+                        apply_modifications,
+                        undo.run_if(
+                            input_pressed(KeyCode::ControlLeft)
+                                .and(input_just_pressed(KeyCode::KeyZ)),
+                        ),
+                        redo.run_if(
+                            input_pressed(KeyCode::ControlLeft)
+                                .and(input_just_pressed(KeyCode::KeyY)),
+                        ),
+                    )
+                        .chain()
+                        .before(chunk_meshing),
                     chunk_meshing,
+                    chunk_mesh_upload.after(chunk_meshing),
+                    visibility_culling.after(chunk_mesh_upload),
+                    mesh_stats.after(visibility_culling),
+                    toggle_meshing_mode
+                        .before(chunk_meshing)
+                        .run_if(input_just_pressed(KeyCode::KeyN)),
                     remove_meshes.run_if(input_just_pressed(KeyCode::KeyU)),
-                    reload_generation_parameters.run_if(input_just_pressed(KeyCode::KeyI)),
+                    trace_terrain_events,
+                    // the jobs started with the old parameters are cancelled before they finish
+                    reload_generation_parameters
+                        .before(chunk_generation)
+                        .before(chunk_generated)
+                        .run_if(input_just_pressed(KeyCode::KeyI)),
                 ),
             )
-            .insert_resource(TerrainGenerator::load_from_file())
+            .add_systems(
+                Last,
+                save_world.run_if(on_timer(AUTOSAVE_PERIOD).or(on_event::<AppExit>)),
+            )
+            .insert_resource(TerrainGenerator::load_from_file(&registry))
+            .insert_resource(WorldSave::open("saves/world", &registry))
+            .insert_resource(registry)
             .insert_resource(Terrain)
+            .init_resource::<MeshingConfig>()
+            .init_resource::<MeshStats>()
+            .init_resource::<TerrainSchedule>()
+            .add_event::<LoaderMoved>()
+            .add_event::<ChunkGenerated>()
+            .add_event::<ChunkMeshed>()
+            .add_event::<ChunkUnloaded>()
+            .add_event::<BlockChanged>()
             .insert_resource(Modifications { queue: Vec::new() })
+            .init_resource::<History>()
             .insert_resource(ChunksIndex {
                 chunks: HashMap::new(),
             });
@@ -106,15 +339,6 @@ impl ChunksIndex {
         let (chunk, local) = global_to_local(global);
         Some((*self.chunks.get(&chunk)?, local))
     }
-    pub fn get(&self, blocks: Query<&ChunkBlocks>, global: IVec3) -> bool {
-        let Some((chunk, local)) = self.global_to_local(global) else {
-            return false;
-        };
-        let Ok(blocks) = blocks.get(chunk) else {
-            return false;
-        };
-        blocks.get(local)
-    }
     // pub fn global_to_local_neighborhood(
     //     &self,
     //     global: IVec3,
@@ -129,171 +353,436 @@ impl ChunksIndex {
     // }
 }
 
+impl TerrainBlocks<'_, '_> {
+    fn chunk(&self, chunk: IVec3) -> Option<&ChunkBlocks> {
+        self.chunks.get(*self.index.chunks.get(&chunk)?).ok()
+    }
+    /// The block at a global coordinate, `None` if its chunk is not loaded
+    pub fn get(&self, global: IVec3) -> Option<Block> {
+        let (chunk, local) = global_to_local(global);
+        Some(self.chunk(chunk)?.get(local))
+    }
+    /// Reads a batch of blocks, successive coordinates in the same chunk share the chunk lookup
+    pub fn get_many<'a>(
+        &'a self,
+        globals: impl IntoIterator<Item = IVec3> + 'a,
+    ) -> impl Iterator<Item = Option<Block>> + 'a {
+        let mut cached = None;
+        globals.into_iter().map(move |global| {
+            let (chunk, local) = global_to_local(global);
+            let blocks = match cached {
+                Some((cached, blocks)) if cached == chunk => blocks,
+                _ => cached.insert((chunk, self.chunk(chunk))).1,
+            };
+            Some(blocks?.get(local))
+        })
+    }
+    /// Iterates over the loaded blocks of the region between `min` and `max` (inclusive)
+    pub fn iter_region(&self, min: IVec3, max: IVec3) -> impl Iterator<Item = (IVec3, Block)> + '_ {
+        let (chunk_min, _) = global_to_local(min);
+        let (chunk_max, _) = global_to_local(max);
+        cuboid(chunk_min, chunk_max)
+            .filter_map(|chunk| Some((chunk, self.chunk(chunk)?)))
+            .flat_map(move |(chunk, blocks)| {
+                let low = (min - chunk * CHUNK_WIDTH).max(IVec3::ZERO);
+                let high = (max - chunk * CHUNK_WIDTH).min(IVec3::splat(CHUNK_WIDTH - 1));
+                cuboid(low, high)
+                    .map(move |local| (local_to_global(chunk, local), blocks.get(local)))
+            })
+    }
+}
+
 impl ChunkBlocks {
-    pub fn get(&self, local: IVec3) -> bool {
-        self.blocks.contains_key(&local)
+    pub fn get(&self, local: IVec3) -> Block {
+        self.blocks.get(local)
     }
-    pub fn remove(&mut self, local: IVec3) {
-        self.blocks.remove(&local);
+    /// Iterates over the non-air blocks with their local coordinate
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, Block)> + '_ {
+        self.blocks.iter()
     }
     pub fn place(&mut self, local: IVec3, block: Block) {
-        if block == Block::Air {
-            self.blocks.remove(&local);
-        } else {
-            self.blocks.insert(local, block);
-        }
+        self.blocks.set(local, block);
+    }
+}
+
+/// Switch between meshing modes, or face ownerships with shift,
+/// and rebuild the shown meshes with the new one
+fn toggle_meshing_mode(
+    mut commands: Commands,
+    mut config: ResMut<MeshingConfig>,
+    mut schedule: ResMut<TerrainSchedule>,
+    keys: Res<ButtonInput<KeyCode>>,
+    meshed: Query<Entity, (With<Chunk>, With<Mesh3d>)>,
+) {
+    if keys.pressed(KeyCode::ShiftLeft) {
+        config.ownership = match config.ownership {
+            FaceOwnership::Blocks => FaceOwnership::PositiveBoundaries,
+            FaceOwnership::PositiveBoundaries => FaceOwnership::Blocks,
+        };
+        // the chunks waiting for a mesh now wait on other neighbours
+        schedule.check_mesh = true;
+    } else {
+        config.mode = match config.mode {
+            MeshingMode::PerFace => MeshingMode::Greedy,
+            MeshingMode::Greedy => MeshingMode::PerFace,
+        };
+    }
+    for chunk in &meshed {
+        commands.entity(chunk).insert(MeshReload { urgent: false });
     }
 }
 
 fn reload_generation_parameters(
     mut commands: Commands,
     mut parameters: ResMut<TerrainGenerator>,
+    registry: Res<BlockRegistry>,
     chunks: Query<Entity, With<Chunk>>,
+    mut loaders: Query<&mut TerrainLoader>,
 ) {
-    *parameters = TerrainGenerator::load_from_file();
+    *parameters = TerrainGenerator::load_from_file(&registry);
+    for mut loader in &mut loaders {
+        loader.reschedule();
+    }
+    // the edit journals are replayed on the regenerated chunks
     for chunk in &chunks {
         commands
             .entity(chunk)
-            .remove::<(ChunkBlocks, Mesh3d, MeshMaterial3d<TerrainMaterial>)>();
+            .remove::<(
+                ChunkBlocks,
+                FaceConnections,
+                Generating,
+                Mesh3d,
+                MeshMaterial3d<TerrainMaterial>,
+                Meshing,
+            )>()
+            .despawn_related::<Children>();
+    }
+}
+
+impl TerrainEditor<'_, '_> {
+    pub fn get(&self, global: IVec3) -> Option<Block> {
+        let (chunk, local) = self.index.global_to_local(global)?;
+        Some(self.chunks.get(chunk).ok()?.get(local))
+    }
+
+    /// Replace the block at a global coordinate, returns the previous one if the chunk is loaded
+    pub fn set(&mut self, global: IVec3, block: Block) -> Option<Block> {
+        let (chunk, local) = self.index.global_to_local(global)?;
+        let mut blocks = self.chunks.get_mut(chunk).ok()?;
+        let previous = blocks.get(local);
+        if previous == block {
+            return Some(previous);
+        }
+        blocks.place(local, block);
+        self.save.record(global_to_local(global).0, local, block);
+        self.changes.write(BlockChanged {
+            at: global,
+            before: previous,
+            after: block,
+        });
+
+        // the faces of the blocks around can be hidden or shaded by this one
+        let ownership = self.config.ownership;
+        let lookups = ownership.lookups();
+        for offset in cuboid(IVec3::NEG_ONE, IVec3::ONE).filter(|&offset| lookups.contains(offset))
+        {
+            let Some((neighbor, local)) = self.index.global_to_local(global + offset) else {
+                continue;
+            };
+            if neighbor == chunk {
+                continue;
+            }
+            let Ok(blocks) = self.chunks.get(neighbor) else {
+                continue;
+            };
+            // the chunks behind the negative boundaries draw the faces of this block lying on them
+            let owner = ownership == FaceOwnership::PositiveBoundaries && offset.min_element() < 0;
+            if !owner && blocks.get(local).is_air() {
+                continue;
+            }
+            self.reload.insert(neighbor);
+        }
+        self.reload.insert(chunk);
+        Some(previous)
+    }
+
+    /// Mark each chunk affected by the edits for a new mesh
+    pub fn finish(&mut self) {
+        for chunk in self.reload.drain() {
+            self.commands
+                .entity(chunk)
+                .insert(MeshReload { urgent: true });
+        }
+    }
+}
+
+impl Modify {
+    /// Corners of the cuboid enclosing the affected voxels
+    fn bounds(self) -> (IVec3, IVec3) {
+        match self {
+            Modify::Remove { at } | Modify::Place { at, .. } | Modify::Set { at, .. } => (at, at),
+            Modify::Fill { from, to, .. }
+            | Modify::Replace { from, to, .. }
+            | Modify::Hollow { from, to, .. } => (from.min(to), from.max(to)),
+            Modify::Sphere { center, radius, .. } => {
+                (center - IVec3::splat(radius), center + IVec3::splat(radius))
+            }
+            Modify::Cylinder {
+                base,
+                radius,
+                height,
+                ..
+            } => (
+                base - IVec3::new(radius, 0, radius),
+                base + IVec3::new(radius, height - 1, radius),
+            ),
+        }
+    }
+
+    /// What a voxel of the bounds should become, `None` to leave it unchanged
+    fn apply(self, registry: &BlockRegistry, at: IVec3, before: Block) -> Option<Block> {
+        match self {
+            Modify::Remove { .. } => (!before.is_air()).then_some(Block::AIR),
+            // liquids give way to placed blocks
+            Modify::Place { block, .. } => {
+                (before.is_air() || registry.properties(before).liquid).then_some(block)
+            }
+            Modify::Set { block, .. } | Modify::Fill { block, .. } => Some(block),
+            Modify::Replace { old, new, .. } => before.same_type(old).then_some(new),
+            Modify::Hollow { block, .. } => {
+                let (min, max) = self.bounds();
+                let wall = at.cmpeq(min).any() || at.cmpeq(max).any();
+                Some(if wall { block } else { Block::AIR })
+            }
+            Modify::Sphere {
+                center,
+                radius,
+                block,
+            } => ((at - center).length_squared() <= radius * radius).then_some(block),
+            Modify::Cylinder {
+                base,
+                radius,
+                block,
+                ..
+            } => ((at - base).xz().length_squared() <= radius * radius).then_some(block),
+        }
     }
 }
 
 fn apply_modifications(
     mut queue: ResMut<Modifications>,
-    index: Res<ChunksIndex>,
-    mut chunks_blocks: Query<&mut ChunkBlocks>,
-    mut commands: Commands,
+    mut editor: TerrainEditor,
+    mut history: ResMut<History>,
+    registry: Res<BlockRegistry>,
 ) {
+    let mut action = Vec::new();
     for modify in std::mem::take(&mut queue.queue) {
-        match modify {
-            Modify::Remove { at } => {
-                let Some((chunk, local)) = index.global_to_local(at) else {
-                    continue;
-                };
-                let Ok(mut blocks) = chunks_blocks.get_mut(chunk) else {
-                    continue;
-                };
-                if !blocks.get(local) {
-                    continue;
-                }
-                blocks.remove(local);
-
-                for neighbor in Sides::AXIS {
-                    let Some((neighbor, local)) = index.global_to_local(at + neighbor) else {
-                        continue;
-                    };
-                    if neighbor == chunk {
-                        continue;
-                    }
-                    let Ok(blocks) = chunks_blocks.get(neighbor) else {
-                        continue;
-                    };
-                    if !blocks.get(local) {
-                        continue;
-                    }
-                    commands.entity(neighbor).insert(MeshReload);
-                }
-                commands.entity(chunk).insert(MeshReload);
+        let (min, max) = modify.bounds();
+        for at in cuboid(min, max) {
+            let Some(before) = editor.get(at) else {
+                continue;
+            };
+            let Some(after) = modify.apply(&registry, at, before) else {
+                continue;
+            };
+            if after != before {
+                editor.set(at, after);
+                action.push(Edit { at, before, after });
             }
-            Modify::Place { at } => {
-                let Some((chunk, local)) = index.global_to_local(at) else {
-                    continue;
-                };
-                let Ok(mut blocks) = chunks_blocks.get_mut(chunk) else {
-                    continue;
-                };
-                if blocks.get(local) {
-                    continue;
-                }
-                blocks.place(local, Block::Stone);
+        }
+    }
+    editor.finish();
+    history.push(action);
+}
 
-                for neighbor in Sides::AXIS {
-                    let Some((neighbor, local)) = index.global_to_local(at + neighbor) else {
-                        continue;
-                    };
-                    if neighbor == chunk {
-                        continue;
-                    }
-                    let Ok(blocks) = chunks_blocks.get(neighbor) else {
-                        continue;
-                    };
-                    if !blocks.get(local) {
-                        continue;
-                    }
-                    commands.entity(neighbor).insert(MeshReload);
-                }
-                commands.entity(chunk).insert(MeshReload);
-            }
+/// Notice the loaders entering another chunk, the terrain being scheduled only then
+fn track_loaders(
+    mut loaders: Query<(Entity, &Transform, &mut TerrainLoader)>,
+    mut moved: EventWriter<LoaderMoved>,
+    mut schedule: ResMut<TerrainSchedule>,
+) {
+    schedule.times = default();
+    for (entity, transform, mut loader) in &mut loaders {
+        let (chunk, _) = global_to_local(transform.translation.floor().as_ivec3());
+        if loader.chunk != Some(chunk) {
+            moved.write(LoaderMoved {
+                loader: entity,
+                from: loader.chunk.replace(chunk),
+                to: chunk,
+            });
         }
     }
 }
 
 fn chunk_indexer(
     loaders: Query<(&Transform, &TerrainLoader)>,
+    mut moved: EventReader<LoaderMoved>,
     mut index: ResMut<ChunksIndex>,
+    mut schedule: ResMut<TerrainSchedule>,
     mut commands: Commands,
 ) {
-    for (transform, loader) in &loaders {
-        let (chunk, _) = global_to_local(transform.translation.as_ivec3());
-        for x in loader.range() {
-            for y in loader.range() {
-                for z in loader.range() {
-                    let chunk = chunk + IVec3 { x, y, z };
-                    if let Entry::Vacant(entry) = index.chunks.entry(chunk) {
-                        entry.insert(
-                            commands
-                                .spawn((
-                                    Transform::from_translation((chunk * CHUNK_WIDTH).as_vec3()),
-                                    Chunk { chunk },
-                                ))
-                                .id(),
-                        );
-                    }
-                }
+    let start = Instant::now();
+    let schedule = &mut *schedule;
+    for event in moved.read() {
+        let Ok((transform, loader)) = loaders.get(event.loader) else {
+            continue;
+        };
+        let mut reached = HashSet::new();
+        for offset in loader.range() {
+            let chunk = event.to + offset;
+            if (transform, loader).outside(Zone::Retain, chunk) {
+                continue;
+            }
+            reached.insert(chunk);
+            if !(transform, loader).inside(Zone::Retain, chunk) {
+                continue;
+            }
+            if let Entry::Vacant(entry) = index.chunks.entry(chunk) {
+                entry.insert(
+                    commands
+                        .spawn((
+                            Transform::from_translation((chunk * CHUNK_WIDTH).as_vec3()),
+                            Chunk { chunk },
+                            Detail::default(),
+                        ))
+                        .id(),
+                );
+            }
+        }
+        // only the chunks it reached before or reaches now can change zones
+        let previous = schedule
+            .reached
+            .insert(event.loader, reached)
+            .unwrap_or_default();
+        let reached = &schedule.reached[&event.loader];
+        for &chunk in previous.difference(reached) {
+            schedule.deload.insert(chunk);
+            schedule.unload.insert(chunk);
+            schedule.detail.insert(chunk);
+        }
+        for &chunk in reached {
+            if (transform, loader).outside(Zone::Mesh, chunk) {
+                schedule.deload.insert(chunk);
             }
+            schedule.detail.insert(chunk);
         }
     }
+    schedule.times.indexer += start.elapsed();
 }
 
 fn chunk_deloader(
     mut commands: Commands,
-    chunks: Query<(Entity, &Chunk), With<Mesh3d>>,
+    meshed: Query<(), MeshedOrMeshing>,
     loaders: Query<(&Transform, &TerrainLoader)>,
+    index: Res<ChunksIndex>,
+    mut schedule: ResMut<TerrainSchedule>,
 ) {
-    for (entity, &Chunk { chunk }) in &chunks {
-        if loaders
-            .iter()
-            .all(|loader| loader.outside(Zone::Mesh, chunk))
+    let start = Instant::now();
+    for chunk in std::mem::take(&mut schedule.deload) {
+        let Some(&entity) = index.chunks.get(&chunk) else {
+            continue;
+        };
+        if meshed.contains(entity)
+            && loaders
+                .iter()
+                .all(|loader| loader.outside(Zone::Mesh, chunk))
         {
             commands
                 .entity(entity)
-                .remove::<(Mesh3d, MeshMaterial3d<TerrainMaterial>)>();
+                .remove::<(Mesh3d, MeshMaterial3d<TerrainMaterial>, Meshing)>()
+                .despawn_related::<Children>();
+        }
+    }
+    schedule.times.deloader += start.elapsed();
+}
+
+/// Forget the chunks far from every loader, their edits staying in the save
+fn chunk_unloader(
+    mut commands: Commands,
+    loaders: Query<(&Transform, &TerrainLoader)>,
+    mut index: ResMut<ChunksIndex>,
+    mut save: ResMut<WorldSave>,
+    mut unloaded: EventWriter<ChunkUnloaded>,
+    mut schedule: ResMut<TerrainSchedule>,
+) {
+    let start = Instant::now();
+    for chunk in std::mem::take(&mut schedule.unload) {
+        let Some(&entity) = index.chunks.get(&chunk) else {
+            continue;
+        };
+        if loaders
+            .iter()
+            .all(|loader| loader.outside(Zone::Retain, chunk))
+        {
+            if let Err(err) = save.unload(chunk) {
+                error!("failed to save an unloaded chunk: {err}");
+            }
+            index.chunks.remove(&chunk);
+            commands.entity(entity).despawn();
+            unloaded.write(ChunkUnloaded { chunk });
         }
     }
+    schedule.times.unloader += start.elapsed();
 }
 
+/// Start generating the chunks nearest to the loaders, and cancel the jobs no longer needed
 fn chunk_generation(
     loaders: Query<(&Transform, &TerrainLoader)>,
-    chunks: Query<(Entity, &Chunk), Without<ChunkBlocks>>,
+    chunks: Query<(Entity, &Chunk, Has<Generating>), Without<ChunkBlocks>>,
+    generating: Query<(), With<Generating>>,
+    mut moved: EventReader<LoaderMoved>,
     mut commands: Commands,
     generator: Res<TerrainGenerator>,
+    mut schedule: ResMut<TerrainSchedule>,
 ) {
-    // TODO: use the chunk wrapper for IVec3
-    let priority = |chunk| {
-        loaders
+    let start = Instant::now();
+    let mut running = generating.iter().count();
+    if !moved.is_empty() {
+        moved.clear();
+        for (entity, &Chunk { chunk }, _) in chunks.iter().filter(|&(_, _, generating)| generating)
+        {
+            if loaders
+                .iter()
+                .all(|loader| loader.outside(Zone::Blocks, chunk))
+            {
+                commands.entity(entity).remove::<Generating>();
+                running -= 1;
+            }
+        }
+        // TODO: use the chunk wrapper for IVec3
+        let priority = |chunk| {
+            loaders
+                .iter()
+                .filter_map(|loader| loader.inside_priority(Zone::Blocks, chunk))
+                .min()
+        };
+        let mut queue = chunks
             .iter()
-            .filter_map(|loader| loader.inside_priority(Zone::Blocks, chunk))
-            .min()
-    };
-    if let Some((entity, chunk, _)) = chunks
-        .iter()
-        .filter_map(|(entity, &Chunk { chunk })| Some((entity, chunk, priority(chunk)?)))
-        .min_by_key(|&(_, _, p)| p)
-    {
-        commands.entity(entity).insert(ChunkBlocks {
-            blocks: generator.generate(chunk),
+            .filter(|&(_, _, generating)| !generating)
+            .filter_map(|(entity, &Chunk { chunk }, _)| Some((entity, priority(chunk)?)))
+            .collect::<Vec<_>>();
+        queue.sort_unstable_by_key(|&(_, p)| std::cmp::Reverse(p));
+        schedule.generation = queue.into_iter().map(|(entity, _)| entity).collect();
+    }
+    let pool = AsyncComputeTaskPool::get();
+    while running < GENERATION_JOBS {
+        let Some(entity) = schedule.generation.pop() else {
+            break;
+        };
+        // the chunk can have been unloaded, or be generated already
+        let Ok((_, &Chunk { chunk }, false)) = chunks.get(entity) else {
+            continue;
+        };
+        let generator = generator.clone();
+        let task = pool.spawn(async move {
+            let blocks = generator.generate(chunk);
+            let connections = FaceConnections::new(&blocks, &generator.registry);
+            (blocks, connections)
         });
+        commands.entity(entity).insert(Generating(task));
+        running += 1;
     }
+    schedule.times.generation += start.elapsed();
     // for (entity, &Chunk { chunk: index }) in &chunks {
     //     if loaders
     //         .iter()
@@ -306,57 +795,173 @@ fn chunk_generation(
     // }
 }
 
-impl<'a> Neighborhood<&'a ChunkBlocks> {
-    fn get(&self, relative: IVec3) -> bool {
-        const CW: i32 = CHUNK_WIDTH;
-        let (chunk, at) = match relative {
-            IVec3 { x: -1, y, z } => (self.x_neg, IVec3 { x: CW - 1, y, z }),
-            IVec3 { x, y: -1, z } => (self.y_neg, IVec3 { x, y: CW - 1, z }),
-            IVec3 { x, y, z: -1 } => (self.z_neg, IVec3 { x, y, z: CW - 1 }),
-            IVec3 { x: CW, y, z } => (self.x_pos, IVec3 { x: 0, y, z }),
-            IVec3 { x, y: CW, z } => (self.y_pos, IVec3 { x, y: 0, z }),
-            IVec3 { x, y, z: CW } => (self.z_pos, IVec3 { x, y, z: 0 }),
-            it => (self.zero, it),
-        };
-        debug_assert!(at.x >= 0);
-        debug_assert!(at.x < CW);
-        debug_assert!(at.y >= 0);
-        debug_assert!(at.y < CW);
-        debug_assert!(at.z >= 0);
-        debug_assert!(at.z < CW);
-        chunk.blocks.get(&at).is_some()
+/// Give their blocks to the chunks whose generation is done
+fn chunk_generated(
+    mut generating: Query<(Entity, &Chunk, &mut Generating)>,
+    meshed: Query<(), MeshedOrMeshing>,
+    index: Res<ChunksIndex>,
+    config: Res<MeshingConfig>,
+    mut commands: Commands,
+    mut save: ResMut<WorldSave>,
+    mut generated: EventWriter<ChunkGenerated>,
+) {
+    for (entity, &Chunk { chunk }, mut task) in &mut generating {
+        if let Some((mut blocks, connections)) = check_ready(&mut task.0) {
+            // the replayed edits are accounted for by the mesh job,
+            // until then every face is seen from all the others
+            let edited = save.apply(chunk, &mut blocks);
+            let mut entity_commands = commands.entity(entity);
+            entity_commands
+                .remove::<(Generating, FaceConnections)>()
+                .insert(blocks);
+            if !edited {
+                entity_commands.insert(connections);
+            }
+            generated.write(ChunkGenerated { chunk, entity });
+            // without waiting on this chunk, the ones after it were meshed with fences cut short
+            if config.ownership == FaceOwnership::PositiveBoundaries {
+                for dir in [IVec3::X, IVec3::Y, IVec3::Z] {
+                    let Some(&after) = index.chunks.get(&(chunk + dir)) else {
+                        continue;
+                    };
+                    if meshed.contains(after) {
+                        commands.entity(after).insert(MeshReload { urgent: false });
+                    }
+                }
+            }
+        }
     }
 }
 
+impl Neighborhood<ChunkBlocks> {
+    /// The block at a coordinate relative to the centre chunk, at most one chunk away from it
+    fn block(&self, relative: IVec3) -> Block {
+        self.try_block(relative)
+            .expect("the lookup is outside of the neighbourhood")
+    }
+
+    /// The block at a coordinate relative to the centre chunk, if its chunk is held
+    fn try_block(&self, relative: IVec3) -> Option<Block> {
+        let (offset, local) = global_to_local(relative);
+        Some(self.get(offset)?.get(local))
+    }
+}
+
+/// Collect the chunks to be meshed around the loaders
+fn collect_mesh_candidates(
+    loaders: Query<(&Transform, &TerrainLoader)>,
+    not_meshed: Query<&Chunk, Unmeshed>,
+    mut schedule: ResMut<TerrainSchedule>,
+) {
+    let start = Instant::now();
+    schedule.mesh = not_meshed
+        .iter()
+        .map(|&Chunk { chunk }| chunk)
+        .filter(|&chunk| {
+            loaders
+                .iter()
+                .any(|loader| loader.inside(Zone::Mesh, chunk))
+        })
+        .collect();
+    schedule.check_mesh = true;
+    schedule.times.need_mesh += start.elapsed();
+}
+
+/// Mark the chunks to be meshed once their neighbours have blocks,
+/// checked again only when chunks are generated
 fn chunk_need_mesh(
+    terrain: TerrainBlocks,
+    config: Res<MeshingConfig>,
+    mut generated: EventReader<ChunkGenerated>,
+    mut schedule: ResMut<TerrainSchedule>,
+    mut commands: Commands,
+) {
+    if generated.is_empty() && !schedule.check_mesh {
+        return;
+    }
+    let start = Instant::now();
+    generated.clear();
+    schedule.check_mesh = false;
+    schedule.mesh.retain(|&chunk| {
+        let Some(&entity) = terrain.index.chunks.get(&chunk) else {
+            return false;
+        };
+        let ready = Neighborhood::new(chunk, config.ownership.neighborhood())
+            .try_map(|chunk| terrain.index.chunks.get(&chunk).copied())
+            .is_some_and(|neighborhood| neighborhood.all(|&chunk| terrain.chunks.contains(chunk)));
+        if ready {
+            commands.entity(entity).insert(MeshReload { urgent: false });
+        }
+        !ready
+    });
+    schedule.times.need_mesh += start.elapsed();
+}
+
+/// Choose the detail of each chunk from its nearest loader, and mesh again those changing it
+fn chunk_detail(
     loaders: Query<(&Transform, &TerrainLoader)>,
-    not_meshed: Query<(Entity, &Chunk), Without<Mesh3d>>,
-    with_blocks: Query<&ChunkBlocks>,
+    mut chunks: Query<&mut Detail>,
+    meshed: Query<(), MeshedOrMeshing>,
     index: Res<ChunksIndex>,
+    mut schedule: ResMut<TerrainSchedule>,
     mut commands: Commands,
 ) {
-    for (entity, &Chunk { chunk }) in &not_meshed {
-        if loaders
-            .iter()
-            .any(|loader| loader.inside(Zone::Mesh, chunk))
-        {
-            let Some(neighborhood) =
-                Neighborhood::from(chunk).try_map(|chunk| index.chunks.get(&chunk).copied())
-            else {
-                continue;
-            };
-            if neighborhood.all(|&chunk| with_blocks.contains(chunk)) {
-                commands.entity(entity).insert(MeshReload);
-            }
+    let start = Instant::now();
+    for chunk in std::mem::take(&mut schedule.detail) {
+        let Some(&entity) = index.chunks.get(&chunk) else {
+            continue;
+        };
+        let Ok(mut detail) = chunks.get_mut(entity) else {
+            continue;
+        };
+        let Some(nearest) = loaders.iter().map(|loader| loader.detail(chunk)).min() else {
+            continue;
+        };
+        if detail.set_if_neq(nearest) && meshed.contains(entity) {
+            commands.entity(entity).insert(MeshReload { urgent: false });
         }
     }
+    schedule.times.detail += start.elapsed();
 }
 
-fn remove_meshes(mut commands: Commands, meshed: Query<Entity, (With<Chunk>, With<Mesh3d>)>) {
+/// Follow the life of the terrain with `RUST_LOG=orbem::terrain=trace`
+fn trace_terrain_events(
+    mut moved: EventReader<LoaderMoved>,
+    mut generated: EventReader<ChunkGenerated>,
+    mut meshed: EventReader<ChunkMeshed>,
+    mut unloaded: EventReader<ChunkUnloaded>,
+    mut changed: EventReader<BlockChanged>,
+) {
+    for LoaderMoved { loader, from, to } in moved.read() {
+        trace!("loader {loader} moved from chunk {from:?} to {to}");
+    }
+    for ChunkGenerated { chunk, entity } in generated.read() {
+        trace!("chunk {chunk} generated in {entity}");
+    }
+    for ChunkMeshed { chunk, entity } in meshed.read() {
+        trace!("chunk {chunk} meshed in {entity}");
+    }
+    for ChunkUnloaded { chunk } in unloaded.read() {
+        trace!("chunk {chunk} unloaded");
+    }
+    for BlockChanged { at, before, after } in changed.read() {
+        trace!("block at {at} changed from {before:?} to {after:?}");
+    }
+}
+
+fn remove_meshes(
+    mut commands: Commands,
+    meshed: Query<Entity, (With<Chunk>, With<Mesh3d>)>,
+    mut loaders: Query<&mut TerrainLoader>,
+) {
     for chunk in meshed {
         commands
             .entity(chunk)
-            .remove::<(Mesh3d, MeshMaterial3d<TerrainMaterial>)>();
+            .remove::<(Mesh3d, MeshMaterial3d<TerrainMaterial>, Meshing)>()
+            .despawn_related::<Children>();
+    }
+    for mut loader in &mut loaders {
+        loader.reschedule();
     }
 }
 
@@ -364,16 +969,17 @@ trait TerrainLoaderExt {
     fn inside(self, zone: Zone, chunk: IVec3) -> bool;
     fn inside_priority(self, zone: Zone, chunk: IVec3) -> Option<u32>;
     fn outside(self, zone: Zone, chunk: IVec3) -> bool;
+    fn detail(self, chunk: IVec3) -> Detail;
 }
 impl<'a> TerrainLoaderExt for (&'a Transform, &'a TerrainLoader) {
     fn inside(self, zone: Zone, chunk: IVec3) -> bool {
         let (tr, loader) = self;
-        zone.distance(chunk, tr.translation) <= loader.radius
+        loader.distance(chunk, tr.translation) <= loader.radius(zone)
     }
     fn inside_priority(self, zone: Zone, chunk: IVec3) -> Option<u32> {
         let (tr, loader) = self;
-        let distance = zone.distance(chunk, tr.translation);
-        if distance <= loader.radius {
+        let distance = loader.distance(chunk, tr.translation);
+        if distance <= loader.radius(zone) {
             Some(distance as u32)
         } else {
             None
@@ -382,20 +988,68 @@ impl<'a> TerrainLoaderExt for (&'a Transform, &'a TerrainLoader) {
 
     fn outside(self, zone: Zone, chunk: IVec3) -> bool {
         let (tr, loader) = self;
-        zone.distance(chunk, tr.translation) > loader.radius + loader.buffer
+        loader.distance(chunk, tr.translation) > loader.radius(zone) + loader.buffer
+    }
+
+    fn detail(self, chunk: IVec3) -> Detail {
+        let (tr, loader) = self;
+        Detail::at_distance(loader.distance(chunk, tr.translation))
     }
 }
 
 impl TerrainLoader {
-    pub fn new(radius: f32, buffer: f32) -> Self {
-        assert!(radius > 1.0);
+    /// Load the terrain along a metric, with the radius of each zone set apart
+    ///
+    /// The blocks zone should reach a chunk past the mesh zone along the metric,
+    /// or the chunks at the edge of the mesh zone wait forever on their neighbours.
+    pub fn with_radii(
+        metric: impl DistanceMetric,
+        mesh: f32,
+        blocks: f32,
+        retain: f32,
+        buffer: f32,
+    ) -> Self {
+        assert!(mesh > 1.0);
+        assert!(mesh <= blocks);
+        assert!(blocks <= retain);
         assert!(buffer > 1.0);
-        Self { radius, buffer }
+        Self {
+            mesh,
+            blocks,
+            retain,
+            buffer,
+            metric: Arc::new(metric),
+            chunk: None,
+        }
     }
-    fn range(self) -> RangeInclusive<i32> {
-        let d = self.radius / CHUNK_WIDTH as f32;
-        let d = d as i32 + 2;
-        -d..=d
+
+    /// Measure the zones with another metric, keeping their radii
+    pub fn set_metric(&mut self, metric: impl DistanceMetric) {
+        self.metric = Arc::new(metric);
+        self.reschedule();
+    }
+
+    fn radius(&self, zone: Zone) -> f32 {
+        match zone {
+            Zone::Mesh => self.mesh,
+            Zone::Blocks => self.blocks,
+            Zone::Retain => self.retain,
+        }
+    }
+
+    /// The distance from the loader at `point` to the centre of a chunk
+    fn distance(&self, chunk: IVec3, point: Vec3) -> f32 {
+        self.metric.distance(chunk_center(chunk) - point)
+    }
+    /// Schedule the terrain around the loader again, as if it had just entered its chunk
+    fn reschedule(&mut self) {
+        self.chunk = None;
+    }
+    /// The offsets of the chunks around the loader's chunk that can be retained
+    fn range(&self) -> impl Iterator<Item = IVec3> {
+        let extent = self.metric.extent(self.retain + self.buffer);
+        let d = (extent / CHUNK_WIDTH as f32).as_ivec3() + 2;
+        cuboid(-d, d)
     }
 }
 
@@ -406,30 +1060,15 @@ enum Zone {
     Blocks,
     /// In the zone, a chunk should render
     Mesh,
-}
-impl Zone {
-    /// Zones are ordered by dependency
-    ///
-    /// A chunk in the Mesh zone should already be in the Blocks zone
-    const fn level(self) -> i32 {
-        match self {
-            Zone::Blocks => 1,
-            Zone::Mesh => 0,
-        }
-    }
-    const fn reach(self) -> f32 {
-        (CHUNK_WIDTH * self.level()) as f32 * 1.05
-    }
-    fn distance(self, chunk: IVec3, point: Vec3) -> f32 {
-        octahedron::distance(chunk_center(chunk), self.reach(), point)
-    }
+    /// In this zone, a chunk keeps its entity and blocks once loaded,
+    /// beyond it is unloaded
+    Retain,
 }
 
 pub fn chunk_center(chunk: IVec3) -> Vec3 {
     (CHUNK_WIDTH * chunk).as_vec3() + Vec3::splat(CHUNK_WIDTH as f32 / 2.0)
 }
 
-#[allow(dead_code)]
 fn local_to_global(chunk: IVec3, local: IVec3) -> IVec3 {
     chunk * CHUNK_WIDTH + local
 }
@@ -439,46 +1078,25 @@ pub fn global_to_local(global: IVec3) -> (IVec3, IVec3) {
 }
 
 impl Block {
-    fn textures(self) -> Option<Sides<u32>> {
-        // 0 stone
-        // 1 dirt
-        // 2 grass side
-        // 3 grass top
-        // 4 sand
-        match self {
-            Block::Air => None,
-            Block::Grass => Some(Sides {
-                x_pos: 2,
-                x_neg: 2,
-                y_pos: 3,
-                y_neg: 1,
-                z_pos: 2,
-                z_neg: 2,
-            }),
-            Block::Stone => Some(Sides {
-                x_pos: 0,
-                x_neg: 0,
-                y_pos: 0,
-                y_neg: 0,
-                z_pos: 0,
-                z_neg: 0,
-            }),
-            Block::Dirt => Some(Sides {
-                x_pos: 1,
-                x_neg: 1,
-                y_pos: 1,
-                y_neg: 1,
-                z_pos: 1,
-                z_neg: 1,
-            }),
-            Block::Sand => Some(Sides {
-                x_pos: 4,
-                x_neg: 4,
-                y_pos: 4,
-                y_neg: 4,
-                z_pos: 4,
-                z_neg: 4,
-            }),
+    pub const AIR: Self = Block::from_id(0);
+
+    const fn from_id(id: u16) -> Self {
+        Self {
+            id,
+            state: BlockState::DEFAULT,
         }
     }
+
+    pub fn is_air(self) -> bool {
+        self.id == 0
+    }
+
+    pub fn with_state(self, state: BlockState) -> Self {
+        Self { state, ..self }
+    }
+
+    /// Whether both blocks are of the same type, whatever their state
+    pub fn same_type(self, other: Self) -> bool {
+        self.id == other.id
+    }
 }
@@ -1,13 +1,12 @@
-use std::fs::File;
+use std::{fs::File, sync::Arc};
 
 use bevy::{
     ecs::resource::Resource,
     math::{IVec2, IVec3, Vec2, Vec3Swizzles},
-    platform::collections::HashMap,
 };
 use serde::Deserialize;
 
-use crate::terrain::{Block, CHUNK_WIDTH, local_to_global};
+use crate::terrain::{Block, BlockRegistry, CHUNK_WIDTH, ChunkBlocks, local_to_global};
 
 fn harmonic_noise(harmonic: &[(f32, f32)], at: Vec2) -> f32 {
     let mut value = 0.0;
@@ -26,10 +25,32 @@ fn logistic(x: f32) -> f32 {
     (1.0 + x.exp()).ln()
 }
 
-#[derive(Resource, Deserialize)]
+/// Content of `assets/generation.json`
+#[derive(Deserialize)]
+struct GeneratorFile {
+    bedrock_harmonics: Vec<(f32, f32)>,
+    relief_harmonics: Vec<(f32, f32)>,
+    layers: Layers<String>,
+}
+
+/// The blocks making up the terrain, from the deepest
+#[derive(Deserialize, Clone)]
+struct Layers<T> {
+    bedrock: T,
+    sediment: T,
+    surface: T,
+    /// Replaces the sediment and surface near the sea level
+    shore: T,
+}
+
+/// Cloned into each generation job
+#[derive(Resource, Clone)]
 pub struct TerrainGenerator {
     bedrock_harmonics: Vec<(f32, f32)>,
     relief_harmonics: Vec<(f32, f32)>,
+    layers: Layers<Block>,
+    /// To find which faces of the generated chunks see each other
+    pub registry: Arc<BlockRegistry>,
 }
 
 struct Profile {
@@ -46,8 +67,25 @@ struct Profile {
 //     }
 // }
 impl TerrainGenerator {
-    pub fn load_from_file() -> Self {
-        serde_json::from_reader(File::open("assets/generation.json").unwrap()).unwrap()
+    pub fn load_from_file(registry: &BlockRegistry) -> Self {
+        let file: GeneratorFile =
+            serde_json::from_reader(File::open("assets/generation.json").unwrap()).unwrap();
+        let find = |name: &str| {
+            registry
+                .find(name)
+                .unwrap_or_else(|| panic!("unknown block {name:?} in assets/generation.json"))
+        };
+        Self {
+            bedrock_harmonics: file.bedrock_harmonics,
+            relief_harmonics: file.relief_harmonics,
+            layers: Layers {
+                bedrock: find(&file.layers.bedrock),
+                sediment: find(&file.layers.sediment),
+                surface: find(&file.layers.surface),
+                shore: find(&file.layers.shore),
+            },
+            registry: Arc::new(registry.clone()),
+        }
     }
     fn sample(&self, coord: IVec2) -> Profile {
         let bedrock = harmonic_noise(&self.bedrock_harmonics, coord.as_vec2());
@@ -69,8 +107,8 @@ impl TerrainGenerator {
             sediment: 3,
         }
     }
-    pub fn generate(&self, chunk: IVec3) -> HashMap<IVec3, Block> {
-        let mut blocks = HashMap::new();
+    pub fn generate(&self, chunk: IVec3) -> ChunkBlocks {
+        let mut blocks = ChunkBlocks::default();
         for x in 0..CHUNK_WIDTH {
             for z in 0..CHUNK_WIDTH {
                 let global = local_to_global(chunk, IVec3 { x, y: 0, z });
@@ -78,20 +116,20 @@ impl TerrainGenerator {
                 let elevation = profile.bedrock;
                 let elevation_relative = elevation - chunk.y * CHUNK_WIDTH;
                 for y in 0..elevation_relative.min(CHUNK_WIDTH) {
-                    blocks.insert(IVec3 { x, y, z }, Block::Stone);
+                    blocks.place(IVec3 { x, y, z }, self.layers.bedrock);
                 }
                 for (y, i) in (elevation_relative..)
                     .zip(0..profile.sediment)
-                    .filter(|&(y, _)| y >= 0 && y < CHUNK_WIDTH)
+                    .filter(|&(y, _)| (0..CHUNK_WIDTH).contains(&y))
                 {
                     let block = if elevation < 2 {
-                        Block::Sand
+                        self.layers.shore
                     } else if i + 1 == profile.sediment {
-                        Block::Grass
+                        self.layers.surface
                     } else {
-                        Block::Dirt
+                        self.layers.sediment
                     };
-                    blocks.insert(IVec3 { x, y, z }, block);
+                    blocks.place(IVec3 { x, y, z }, block);
                 }
             }
         }
@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::terrain::{Block, TerrainEditor};
+
+/// How many edits the actions that can be undone or redone hold together
+const HISTORY_LIMIT: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Edit {
+    pub at: IVec3,
+    pub before: Block,
+    pub after: Block,
+}
+
+/// The edits applied in response to a single player action
+pub type Action = Vec<Edit>;
+
+/// Applied actions that can be undone, and undone ones that can be redone
+#[derive(Resource, Default)]
+pub struct History {
+    undo: VecDeque<Action>,
+    redo: Vec<Action>,
+    /// Edits held by all the actions
+    edits: usize,
+}
+
+impl History {
+    /// A new action makes the undone ones unreachable,
+    /// and the oldest ones are forgotten past the limit, the new one too if too large alone
+    pub fn push(&mut self, action: Action) {
+        if action.is_empty() {
+            return;
+        }
+        self.edits += action.len();
+        self.edits -= self
+            .redo
+            .drain(..)
+            .map(|action| action.len())
+            .sum::<usize>();
+        self.undo.push_back(action);
+        while self.edits > HISTORY_LIMIT {
+            let Some(oldest) = self.undo.pop_front() else {
+                break;
+            };
+            self.edits -= oldest.len();
+        }
+    }
+}
+
+/// Whether every block of an action is loaded, an action is kept until it can be applied whole
+fn loaded(editor: &TerrainEditor, action: &Action) -> bool {
+    action.iter().all(|edit| editor.get(edit.at).is_some())
+}
+
+pub fn undo(mut history: ResMut<History>, mut editor: TerrainEditor) {
+    let Some(action) = history.undo.pop_back() else {
+        return;
+    };
+    if !loaded(&editor, &action) {
+        history.undo.push_back(action);
+        return;
+    }
+    for edit in action.iter().rev() {
+        editor.set(edit.at, edit.before);
+    }
+    editor.finish();
+    history.redo.push(action);
+}
+
+pub fn redo(mut history: ResMut<History>, mut editor: TerrainEditor) {
+    let Some(action) = history.redo.pop() else {
+        return;
+    };
+    if !loaded(&editor, &action) {
+        history.redo.push(action);
+        return;
+    }
+    for edit in &action {
+        editor.set(edit.at, edit.after);
+    }
+    editor.finish();
+    history.undo.push_back(action);
+}
@@ -0,0 +1,97 @@
+use std::io::{self, Read, Write};
+
+use bevy::{platform::collections::HashMap, prelude::*};
+
+use crate::terrain::{Block, BlockState, CHUNK_WIDTH, ChunkBlocks};
+
+/// Beyond this amount of entries, the journal is folded into its snapshot
+const COMPACTION_THRESHOLD: usize = 1024;
+
+/// The edits made to a chunk, replayed on top of the generated blocks
+///
+/// Edits are appended in order as they are applied. Once there are too many,
+/// they are compacted into a snapshot holding the last block written at each position.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    snapshot: HashMap<IVec3, Block>,
+    entries: Vec<(IVec3, Block)>,
+}
+
+impl Journal {
+    pub fn record(&mut self, local: IVec3, block: Block) {
+        self.entries.push((local, block));
+        if self.entries.len() > COMPACTION_THRESHOLD {
+            self.compact();
+        }
+    }
+
+    fn compact(&mut self) {
+        self.snapshot.extend(self.entries.drain(..));
+    }
+
+    pub fn apply(&self, blocks: &mut ChunkBlocks) {
+        for (local, block) in self.snapshot().chain(self.entries.iter().copied()) {
+            blocks.place(local, block);
+        }
+    }
+
+    fn snapshot(&self) -> impl Iterator<Item = (IVec3, Block)> + '_ {
+        self.snapshot.iter().map(|(&local, &block)| (local, block))
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        write_edits(out, self.snapshot.len(), self.snapshot())?;
+        write_edits(out, self.entries.len(), self.entries.iter().copied())
+    }
+
+    /// `blocks` maps the block ids found in the file to the current ones
+    pub fn read(input: &mut impl Read, blocks: &[Block]) -> io::Result<Self> {
+        let snapshot = read_edits(input, blocks)?.into_iter().collect();
+        let entries = read_edits(input, blocks)?;
+        Ok(Self { snapshot, entries })
+    }
+}
+
+fn write_edits(
+    out: &mut impl Write,
+    len: usize,
+    edits: impl Iterator<Item = (IVec3, Block)>,
+) -> io::Result<()> {
+    out.write_all(&(len as u32).to_le_bytes())?;
+    for (local, block) in edits {
+        let packed = (local.y * CHUNK_WIDTH + local.z) * CHUNK_WIDTH + local.x;
+        out.write_all(&(packed as u16).to_le_bytes())?;
+        out.write_all(&block.id.to_le_bytes())?;
+        out.write_all(&[block.state.to_bits()])?;
+    }
+    Ok(())
+}
+
+fn read_edits(input: &mut impl Read, blocks: &[Block]) -> io::Result<Vec<(IVec3, Block)>> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut len = [0; 4];
+    input.read_exact(&mut len)?;
+    (0..u32::from_le_bytes(len))
+        .map(|_| {
+            let mut edit = [0; 5];
+            input.read_exact(&mut edit)?;
+            let packed = u16::from_le_bytes([edit[0], edit[1]]) as i32;
+            let local = IVec3 {
+                x: packed % CHUNK_WIDTH,
+                z: packed / CHUNK_WIDTH % CHUNK_WIDTH,
+                y: packed / (CHUNK_WIDTH * CHUNK_WIDTH),
+            };
+            if local.y >= CHUNK_WIDTH {
+                return Err(invalid("edit position out of the chunk"));
+            }
+            let id = u16::from_le_bytes([edit[2], edit[3]]);
+            let block = blocks
+                .get(id as usize)
+                .ok_or_else(|| invalid("unknown block id"))?;
+            let state =
+                BlockState::from_bits(edit[4]).ok_or_else(|| invalid("invalid block state"))?;
+            let block = block.with_state(state);
+            Ok((local, block))
+        })
+        .collect()
+}
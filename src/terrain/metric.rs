@@ -0,0 +1,74 @@
+use bevy::prelude::*;
+
+use crate::octahedron;
+
+/// How far a chunk is from a loader, the zones around the loader being the balls of this distance
+pub trait DistanceMetric: Send + Sync + 'static {
+    /// The distance of an offset from the loader
+    fn distance(&self, offset: Vec3) -> f32;
+
+    /// Half the size of the box enclosing the ball of a radius
+    fn extent(&self, radius: f32) -> Vec3 {
+        Vec3::splat(radius)
+    }
+}
+
+/// The sum of the distances along the axes, the zones being octahedra
+#[derive(Debug, Clone, Copy)]
+pub struct Octahedral;
+
+/// The straight line distance, the zones being spheres
+#[derive(Debug, Clone, Copy)]
+pub struct Spherical;
+
+/// The horizontal distance, or the vertical one scaled, the zones being vertical cylinders
+/// with the one of `radius` reaching `half_height` up and down
+#[derive(Debug, Clone, Copy)]
+pub struct Cylindrical {
+    radius: f32,
+    half_height: f32,
+}
+
+/// The largest distance along an axis, the zones being cubes
+#[derive(Debug, Clone, Copy)]
+pub struct Cubic;
+
+impl Cylindrical {
+    pub const fn new(radius: f32, half_height: f32) -> Self {
+        assert!(radius > 0.0, "cylinders must have a width");
+        assert!(half_height > 0.0, "cylinders must have a height");
+        Self {
+            radius,
+            half_height,
+        }
+    }
+}
+
+impl DistanceMetric for Octahedral {
+    fn distance(&self, offset: Vec3) -> f32 {
+        octahedron::norm(offset)
+    }
+}
+
+impl DistanceMetric for Spherical {
+    fn distance(&self, offset: Vec3) -> f32 {
+        offset.length()
+    }
+}
+
+impl DistanceMetric for Cylindrical {
+    fn distance(&self, offset: Vec3) -> f32 {
+        let vertical = offset.y.abs() * self.radius / self.half_height;
+        offset.xz().length().max(vertical)
+    }
+
+    fn extent(&self, radius: f32) -> Vec3 {
+        Vec3::new(radius, radius * self.half_height / self.radius, radius)
+    }
+}
+
+impl DistanceMetric for Cubic {
+    fn distance(&self, offset: Vec3) -> f32 {
+        offset.abs().max_element()
+    }
+}
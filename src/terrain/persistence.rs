@@ -0,0 +1,224 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use bevy::{
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+
+use crate::terrain::{Block, BlockRegistry, ChunkBlocks, journal::Journal};
+
+/// Number of chunks along each axis of a region file
+const REGION_WIDTH: i32 = 8;
+const MAGIC: &[u8; 4] = b"ORBR";
+const VERSION: u16 = 4;
+pub const AUTOSAVE_PERIOD: Duration = Duration::from_secs(30);
+
+/// The edit journals of the chunks modified by players, grouped in region files on disk
+///
+/// Regions are read lazily the first time one of their chunk is requested,
+/// and only regions with new content are written back. A region is dropped from memory
+/// once all its chunks are unloaded.
+///
+/// Each region starts with the names of the blocks indexed by their id,
+/// so that saves survive blocks being added to or removed from the registry.
+#[derive(Resource)]
+pub struct WorldSave {
+    directory: PathBuf,
+    /// Block names of the running registry, indexed by id
+    names: Vec<String>,
+    regions: HashMap<IVec3, HashMap<IVec3, Journal>>,
+    dirty: HashSet<IVec3>,
+    /// The chunks of each region that are loaded in the world
+    loaded: HashMap<IVec3, HashSet<IVec3>>,
+}
+
+fn chunk_to_region(chunk: IVec3) -> IVec3 {
+    chunk.div_euclid(IVec3::splat(REGION_WIDTH))
+}
+
+impl WorldSave {
+    pub fn open(directory: impl Into<PathBuf>, registry: &BlockRegistry) -> Self {
+        Self {
+            directory: directory.into(),
+            names: registry.iter().map(|(_, def)| def.name.clone()).collect(),
+            regions: HashMap::new(),
+            dirty: HashSet::new(),
+            loaded: HashMap::new(),
+        }
+    }
+
+    fn region_path(&self, region: IVec3) -> PathBuf {
+        self.directory
+            .join(format!("r.{}.{}.{}.region", region.x, region.y, region.z))
+    }
+
+    fn region(&mut self, region: IVec3) -> &mut HashMap<IVec3, Journal> {
+        if !self.regions.contains_key(&region) {
+            let path = self.region_path(region);
+            let chunks = match File::open(&path) {
+                Ok(file) => {
+                    read_region(&mut BufReader::new(file), &self.names).unwrap_or_else(|err| {
+                        warn!("ignoring corrupted region {}: {err}", path.display());
+                        HashMap::new()
+                    })
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+                Err(err) => {
+                    warn!("failed to open region {}: {err}", path.display());
+                    HashMap::new()
+                }
+            };
+            self.regions.insert(region, chunks);
+        }
+        self.regions.get_mut(&region).unwrap()
+    }
+
+    /// Replay the edits made to a chunk on its freshly generated blocks, returns whether it had any
+    pub fn apply(&mut self, chunk: IVec3, blocks: &mut ChunkBlocks) -> bool {
+        let region = chunk_to_region(chunk);
+        self.loaded.entry(region).or_default().insert(chunk);
+        let journal = self.region(region).get(&chunk);
+        if let Some(journal) = journal {
+            journal.apply(blocks);
+        }
+        journal.is_some()
+    }
+
+    /// Forget a chunk leaving the world
+    ///
+    /// When it was the last loaded chunk of its region, the region is written if needed,
+    /// then dropped from memory.
+    pub fn unload(&mut self, chunk: IVec3) -> io::Result<()> {
+        let region = chunk_to_region(chunk);
+        let Some(loaded) = self.loaded.get_mut(&region) else {
+            return Ok(());
+        };
+        loaded.remove(&chunk);
+        if !loaded.is_empty() {
+            return Ok(());
+        }
+        if self.dirty.contains(&region) {
+            fs::create_dir_all(&self.directory)?;
+            self.write(region)?;
+            self.dirty.remove(&region);
+        }
+        self.loaded.remove(&region);
+        self.regions.remove(&region);
+        Ok(())
+    }
+
+    pub fn record(&mut self, chunk: IVec3, local: IVec3, block: Block) {
+        let region = chunk_to_region(chunk);
+        self.region(region)
+            .entry(chunk)
+            .or_default()
+            .record(local, block);
+        self.dirty.insert(region);
+    }
+
+    /// Write all regions with unsaved content to disk
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.directory)?;
+        // a region failing to be written stays dirty for the next flush
+        for region in self.dirty.clone() {
+            self.write(region)?;
+            self.dirty.remove(&region);
+        }
+        Ok(())
+    }
+
+    fn write(&self, region: IVec3) -> io::Result<()> {
+        let path = self.region_path(region);
+        // written aside then renamed, so a crash never leaves a truncated region
+        let partial = path.with_extension("region.partial");
+        let mut out = BufWriter::new(File::create(&partial)?);
+        write_region(&mut out, &self.names, &self.regions[&region])?;
+        out.into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(partial, path)
+    }
+}
+
+fn write_region(
+    out: &mut impl Write,
+    names: &[String],
+    chunks: &HashMap<IVec3, Journal>,
+) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&(names.len() as u16).to_le_bytes())?;
+    for name in names {
+        out.write_all(&(name.len() as u16).to_le_bytes())?;
+        out.write_all(name.as_bytes())?;
+    }
+    out.write_all(&(chunks.len() as u32).to_le_bytes())?;
+    for (chunk, journal) in chunks {
+        for coord in chunk.to_array() {
+            out.write_all(&coord.to_le_bytes())?;
+        }
+        journal.write(out)?;
+    }
+    Ok(())
+}
+
+/// Read a region, translating its block ids to the ones of `names`
+///
+/// Blocks that no longer exist are replaced with air.
+fn read_region(input: &mut impl Read, names: &[String]) -> io::Result<HashMap<IVec3, Journal>> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a region file"));
+    }
+    let mut version = [0; 2];
+    input.read_exact(&mut version)?;
+    if u16::from_le_bytes(version) != VERSION {
+        return Err(invalid("unsupported region version"));
+    }
+    let mut len = [0; 2];
+    input.read_exact(&mut len)?;
+    let blocks = (0..u16::from_le_bytes(len))
+        .map(|_| {
+            input.read_exact(&mut len)?;
+            let mut name = vec![0; u16::from_le_bytes(len) as usize];
+            input.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| invalid("invalid block name"))?;
+            Ok(match names.iter().position(|known| *known == name) {
+                Some(id) => Block::from_id(id as u16),
+                None => {
+                    warn!("unknown block {name:?} in region, replaced with air");
+                    Block::AIR
+                }
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut count = [0; 4];
+    input.read_exact(&mut count)?;
+    let mut chunks = HashMap::new();
+    for _ in 0..u32::from_le_bytes(count) {
+        let mut coords = [0; 3];
+        for coord in &mut coords {
+            let mut bytes = [0; 4];
+            input.read_exact(&mut bytes)?;
+            *coord = i32::from_le_bytes(bytes);
+        }
+        chunks.insert(IVec3::from_array(coords), Journal::read(input, &blocks)?);
+    }
+    Ok(chunks)
+}
+
+pub fn save_world(mut save: ResMut<WorldSave>) {
+    if let Err(err) = save.flush() {
+        error!("failed to save the world: {err}");
+    }
+}
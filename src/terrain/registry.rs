@@ -0,0 +1,293 @@
+use std::fs::File;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    spacial::{Side, Sides},
+    terrain::{
+        Block,
+        shape::{Boxes, Shape},
+        state::StateKind,
+    },
+};
+
+/// Content of `assets/blocks.json`
+#[derive(Deserialize)]
+struct RegistryFile {
+    /// Names of the textures, in the order they are stacked in the blocks texture
+    textures: Vec<String>,
+    blocks: Vec<BlockEntry>,
+}
+
+#[derive(Deserialize)]
+struct BlockEntry {
+    name: String,
+    textures: FaceTextures,
+    #[serde(default)]
+    state: StateKind,
+    #[serde(default)]
+    shape: Shape,
+    #[serde(default)]
+    properties: BlockProperties,
+}
+
+/// Texture names per face, the most specific one given wins
+///
+/// The faces are the ones of the model in its default state, blocks with a facing
+/// have their front at `z_pos`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaceTextures {
+    all: Option<String>,
+    side: Option<String>,
+    top: Option<String>,
+    bottom: Option<String>,
+    x_pos: Option<String>,
+    x_neg: Option<String>,
+    y_pos: Option<String>,
+    y_neg: Option<String>,
+    z_pos: Option<String>,
+    z_neg: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlockProperties {
+    /// Offered in the hotbar
+    pub placeable: bool,
+    /// Stops moving bodies, passable blocks such as plants are walked through
+    pub solid: bool,
+    pub opacity: Opacity,
+    /// Not targeted by the player, and never solid
+    pub liquid: bool,
+    /// Emitted light level, from 0 to 15
+    pub light: u8,
+    /// Seconds the block has to be mined before breaking, only shown as blocks still break at once
+    pub hardness: f32,
+}
+
+/// How much a block lets see what is behind it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Opacity {
+    Opaque,
+    /// Either fully opaque or fully transparent depending on the texel
+    Cutout,
+    /// Blended with what is behind
+    Translucent,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockDef {
+    pub name: String,
+    /// Texture index of each face, `None` for blocks that are not drawn
+    pub textures: Option<Sides<u32>>,
+    pub state: StateKind,
+    pub shape: Shape,
+    pub properties: BlockProperties,
+}
+
+/// All the block types, the id of a block being its index
+///
+/// Air always comes first, the other blocks follow in the order of `assets/blocks.json`.
+#[derive(Resource, Debug, Clone)]
+pub struct BlockRegistry {
+    blocks: Vec<BlockDef>,
+    texture_count: usize,
+}
+
+impl Default for BlockProperties {
+    /// A solid opaque cube
+    fn default() -> Self {
+        Self {
+            placeable: false,
+            solid: true,
+            opacity: Opacity::Opaque,
+            liquid: false,
+            light: 0,
+            hardness: 0.0,
+        }
+    }
+}
+
+impl FaceTextures {
+    fn resolve(&self, textures: &[String]) -> Result<Sides<u32>, String> {
+        let side = |specific: &Option<String>, group: &Option<String>| {
+            let name = specific
+                .as_ref()
+                .or(group.as_ref())
+                .or(self.all.as_ref())
+                .ok_or("missing face texture")?;
+            let index = textures
+                .iter()
+                .position(|texture| texture == name)
+                .ok_or_else(|| format!("unknown texture {name:?}"))?;
+            Ok::<_, String>(index as u32)
+        };
+        Ok(Sides {
+            x_pos: side(&self.x_pos, &self.side)?,
+            x_neg: side(&self.x_neg, &self.side)?,
+            y_pos: side(&self.y_pos, &self.top)?,
+            y_neg: side(&self.y_neg, &self.bottom)?,
+            z_pos: side(&self.z_pos, &self.side)?,
+            z_neg: side(&self.z_neg, &self.side)?,
+        })
+    }
+}
+
+impl BlockRegistry {
+    pub fn load_from_file() -> Self {
+        let file: RegistryFile =
+            serde_json::from_reader(File::open("assets/blocks.json").unwrap()).unwrap();
+        Self::from_file(file).unwrap_or_else(|err| panic!("invalid assets/blocks.json: {err}"))
+    }
+
+    fn from_file(file: RegistryFile) -> Result<Self, String> {
+        let mut blocks = Vec::from([BlockDef {
+            name: "air".to_string(),
+            textures: None,
+            state: StateKind::None,
+            shape: Shape::Cube,
+            properties: BlockProperties {
+                solid: false,
+                // nothing to see there
+                opacity: Opacity::Translucent,
+                ..default()
+            },
+        }]);
+        for entry in file.blocks {
+            if blocks.iter().any(|block| block.name == entry.name) {
+                return Err(format!("block {:?} is defined twice", entry.name));
+            }
+            let textures = entry
+                .textures
+                .resolve(&file.textures)
+                .map_err(|err| format!("block {:?}: {err}", entry.name))?;
+            let properties = entry.properties;
+            if properties.liquid && properties.solid {
+                return Err(format!("liquid block {:?} cannot be solid", entry.name));
+            }
+            if properties.light > 15 {
+                return Err(format!("block {:?} emits more than 15 light", entry.name));
+            }
+            blocks.push(BlockDef {
+                name: entry.name,
+                textures: Some(textures),
+                state: entry.state,
+                shape: entry.shape,
+                properties,
+            });
+        }
+        if blocks.len() > u16::MAX as usize {
+            return Err("too many blocks".to_string());
+        }
+        Ok(Self {
+            blocks,
+            texture_count: file.textures.len(),
+        })
+    }
+
+    pub fn get(&self, block: Block) -> &BlockDef {
+        &self.blocks[block.id as usize]
+    }
+
+    pub fn find(&self, name: &str) -> Option<Block> {
+        let index = self.blocks.iter().position(|block| block.name == name)?;
+        Some(Block::from_id(index as u16))
+    }
+
+    pub fn name(&self, block: Block) -> &str {
+        &self.get(block).name
+    }
+
+    pub fn properties(&self, block: Block) -> &BlockProperties {
+        &self.get(block).properties
+    }
+
+    pub fn is_solid(&self, block: Block) -> bool {
+        self.properties(block).solid
+    }
+
+    /// Whether the player can point at the block to break it or build against it
+    pub fn is_targetable(&self, block: Block) -> bool {
+        !block.is_air() && !self.properties(block).liquid
+    }
+
+    /// Whether nothing can be seen through the block
+    pub fn is_opaque(&self, block: Block) -> bool {
+        self.get(block).shape.is_full() && self.properties(block).opacity == Opacity::Opaque
+    }
+
+    /// Whether the block darkens the corners of the faces next to it
+    pub fn occludes(&self, block: Block) -> bool {
+        self.get(block).shape.is_full() && self.properties(block).opacity != Opacity::Translucent
+    }
+
+    /// Whether the face of `block` touching `neighbour` is hidden by it
+    ///
+    /// Only opaque blocks filling their voxel hide what is behind them, except that the
+    /// faces between two translucent blocks of the same kind are not drawn, as within water.
+    pub fn face_hidden(&self, block: Block, neighbour: Block) -> bool {
+        if !self.get(neighbour).shape.is_full() {
+            return false;
+        }
+        match self.properties(neighbour).opacity {
+            Opacity::Opaque => true,
+            Opacity::Cutout => false,
+            Opacity::Translucent => block == neighbour,
+        }
+    }
+
+    /// Texture index and rotation in quarter turns of each face, turned according to the state
+    pub fn textures(&self, block: Block) -> Option<Sides<(u32, usize)>> {
+        let def = self.get(block);
+        let textures = def.textures?;
+        Some(
+            def.state
+                .orient(block.state)
+                .map(|(side, turns)| (textures[side], turns)),
+        )
+    }
+
+    /// The texture shown to represent a block
+    pub fn icon(&self, block: Block) -> Option<u32> {
+        Some(self.get(block).textures?.x_pos)
+    }
+
+    /// The boxes making up a block in its voxel, `neighbour` giving the blocks around it
+    pub fn boxes(&self, block: Block, neighbour: impl Fn(Side) -> Option<Block>) -> Boxes {
+        self.get(block)
+            .shape
+            .boxes(block.state, |side| self.connects(neighbour(side)))
+    }
+
+    /// The boxes outlining a block when pointed at
+    pub fn outline(&self, block: Block, neighbour: impl Fn(Side) -> Option<Block>) -> Boxes {
+        self.get(block)
+            .shape
+            .outline(block.state, |side| self.connects(neighbour(side)))
+    }
+
+    /// Whether fences join a neighbour
+    fn connects(&self, neighbour: Option<Block>) -> bool {
+        neighbour.is_some_and(|neighbour| {
+            let def = self.get(neighbour);
+            def.shape == Shape::Fence || (def.shape.is_full() && def.properties.solid)
+        })
+    }
+
+    /// The block with the state it gets when placed, see `StateKind::placed`
+    pub fn placed(&self, block: Block, dir: IVec3, point: Vec3, ray: Vec3) -> Block {
+        block.with_state(self.get(block).state.placed(dir, point, ray))
+    }
+
+    /// Number of textures stacked in the blocks texture
+    pub fn texture_count(&self) -> usize {
+        self.texture_count
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Block, &BlockDef)> {
+        (0..).map(Block::from_id).zip(&self.blocks)
+    }
+}
@@ -1,11 +1,18 @@
 use crate::{
-    spacial::{Neighborhood, Side, Sides},
-    terrain::{CHUNK_WIDTH, Chunk, ChunkBlocks, ChunksIndex, MeshReload},
+    spacial::{Neighborhood, NeighborhoodSize, Side, Sides, cuboid},
+    terrain::{
+        Block, BlockRegistry, CHUNK_WIDTH, Chunk, ChunkBlocks, ChunkMeshed, MeshReload,
+        TerrainBlocks,
+        registry::Opacity,
+        shape::{BlockBox, Shape},
+        visibility::FaceConnections,
+    },
 };
 use bevy::{
     asset::RenderAssetUsages,
     image::{CompressedImageFormats, ImageSampler},
     pbr::{MaterialPipeline, MaterialPipelineKey},
+    platform::{collections::HashMap, time::Instant},
     prelude::*,
     reflect::TypePath,
     render::{
@@ -17,22 +24,128 @@ use bevy::{
             AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
         },
     },
+    tasks::{AsyncComputeTaskPool, ComputeTaskPool, Task, futures::check_ready},
 };
+use std::{sync::Arc, time::Duration};
+
+/// How many chunks can be meshed at the same time
+const MESHING_JOBS: usize = 16;
+/// Time the main thread can spend each frame handing finished meshes to the renderer
+const UPLOAD_BUDGET: Duration = Duration::from_millis(2);
 
 #[derive(Resource)]
 pub struct MeshAssets {
     material: Handle<TerrainMaterial>,
+    translucent_material: Handle<TerrainMaterial>,
+    /// Shared with the meshing jobs
+    registry: Arc<BlockRegistry>,
+}
+
+/// How chunk meshes are built, switched at runtime to compare
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MeshingConfig {
+    pub mode: MeshingMode,
+    pub ownership: FaceOwnership,
+}
+
+/// How the faces of full blocks are turned into quads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeshingMode {
+    /// One quad per visible face
+    PerFace,
+    /// Coplanar faces with the same texture are merged into larger quads
+    #[default]
+    Greedy,
+}
+
+/// Which chunk draws the faces lying on the boundary between two chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FaceOwnership {
+    /// Each chunk draws the faces of its blocks, shaded by the blocks around,
+    /// and waits on all the chunks around it
+    #[default]
+    Blocks,
+    /// Each chunk draws the faces on its positive boundaries and none on its negative ones,
+    /// so it only waits on its `+X`, `+Y` and `+Z` neighbours
+    ///
+    /// Ambient occlusion would need the chunks on every side, so it is left out.
+    PositiveBoundaries,
+}
+
+/// How many voxels are merged into each cell of a chunk mesh, far chunks being downsampled
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Detail {
+    #[default]
+    Full,
+    Half,
+    Quarter,
+    Eighth,
 }
 
+/// Size of the terrain meshes currently shown, to compare the meshing modes
+#[derive(Resource, Debug, Default)]
+pub struct MeshStats {
+    pub config: MeshingConfig,
+    pub vertices: usize,
+    pub indices: usize,
+    /// Meshed chunks hidden from the camera by the chunks around it
+    pub hidden: usize,
+}
+
+/// The meshes of a chunk being built on the task pool, removing it cancels the job
+#[derive(Component)]
+pub struct Meshing {
+    task: Task<(ChunkMeshes, FaceConnections)>,
+}
+
+/// The built meshes of a chunk, the translucent one only if it has faces
+struct ChunkMeshes {
+    opaque: Mesh,
+    translucent: Option<Mesh>,
+}
+
+/// The translucent faces of a chunk, child of the chunk entity
+///
+/// They are drawn apart from the rest of the chunk to be blended with what is behind.
+#[derive(Component)]
+pub struct TranslucentPart;
+
+/// Vertex data of a mesh being built
+#[derive(Default)]
+struct MeshBuffers {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+    texture_uvs: Vec<[f32; 2]>,
+    texture_indices: Vec<u32>,
+    ambient_occlusion: Vec<f32>,
+    /// Faces of full blocks waiting to be merged
+    merged: Sides<HashMap<IVec3, MergedFace>>,
+}
+
+/// Texture and rotation of a face with the light reaching its corners,
+/// only faces with the same ones are merged
+type MergedFace = ((u32, usize), [u8; 4]);
+
 pub fn setup_render(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
     mut materials: ResMut<Assets<TerrainMaterial>>,
+    registry: Res<BlockRegistry>,
 ) {
+    let texture = images.add(load_texture_atlas());
     commands.insert_resource(MeshAssets {
         material: materials.add(TerrainMaterial {
-            texture: images.add(load_texture_atlas()),
+            texture: texture.clone(),
+            alpha_cutoff: 0.5,
+            alpha_mode: AlphaMode::Mask(0.5),
         }),
+        translucent_material: materials.add(TerrainMaterial {
+            texture,
+            alpha_cutoff: 0.0,
+            alpha_mode: AlphaMode::Blend,
+        }),
+        registry: Arc::new(registry.clone()),
     });
 }
 fn load_texture_atlas() -> Image {
@@ -50,69 +163,474 @@ fn load_texture_atlas() -> Image {
     textures
 }
 
+/// Start meshing the chunks needing it on the task pool,
+/// and mesh the edited ones at once for the edit to show up the next frame
 pub fn chunk_meshing(
-    not_meshed: Query<(Entity, &Chunk), With<MeshReload>>,
-    with_blocks: Query<&ChunkBlocks>,
-    index: Res<ChunksIndex>,
+    not_meshed: Query<(Entity, &Chunk, &MeshReload, &Detail)>,
+    meshing: Query<(), With<Meshing>>,
+    terrain: TerrainBlocks,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     assets: Res<MeshAssets>,
+    config: Res<MeshingConfig>,
 ) {
-    for (entity, &Chunk { chunk }) in &not_meshed {
-        let Some(neighborhood) = Neighborhood::from(chunk)
-            .try_map(|chunk| with_blocks.get(*index.chunks.get(&chunk)?).ok())
-        else {
+    let config = *config;
+    let pool = AsyncComputeTaskPool::get();
+    let mut running = meshing.iter().count();
+    let mut urgent = Vec::new();
+    for (entity, &Chunk { chunk }, reload, &detail) in &not_meshed {
+        if !reload.urgent && running >= MESHING_JOBS {
+            continue;
+        }
+        if !Neighborhood::new(chunk, config.ownership.neighborhood())
+            .all(|&chunk| terrain.chunk(chunk).is_some())
+        {
+            continue;
+        }
+        // the job works on a copy, the chunks can be edited meanwhile
+        let snapshot = Neighborhood::new(chunk, config.ownership.lookups())
+            .filter_map(|chunk| terrain.chunk(chunk).cloned());
+        if reload.urgent {
+            urgent.push((entity, chunk, snapshot, detail));
+            continue;
+        }
+        let registry = assets.registry.clone();
+        let task = pool.spawn(async move {
+            let connections = FaceConnections::new(snapshot.centre(), &registry);
+            (
+                build_meshes(&registry, &snapshot, config, detail),
+                connections,
+            )
+        });
+        // replacing an older job cancels it, it would overwrite the chunk with a stale mesh
+        commands
+            .entity(entity)
+            .remove::<MeshReload>()
+            .insert(Meshing { task });
+        running += 1;
+    }
+    if urgent.is_empty() {
+        return;
+    }
+    // spread over the compute threads, apart from the jobs of the async pool
+    let registry = &assets.registry;
+    let built = ComputeTaskPool::get().scope(|scope| {
+        for (_, _, snapshot, detail) in &urgent {
+            scope.spawn(async move {
+                let connections = FaceConnections::new(snapshot.centre(), registry);
+                (
+                    build_meshes(registry, snapshot, config, *detail),
+                    connections,
+                )
+            });
+        }
+    });
+    for ((entity, chunk, ..), (built, connections)) in urgent.into_iter().zip(built) {
+        // an older job would overwrite the edit with a stale mesh once done
+        let mut entity = commands.entity(entity);
+        entity.remove::<(MeshReload, Meshing)>().insert(connections);
+        attach_meshes(&mut entity, chunk, built, &mut meshes, &assets);
+    }
+}
+
+/// Give their finished meshes to the chunks within the time budget of the frame
+pub fn chunk_mesh_upload(
+    mut meshing: Query<(Entity, &Chunk, &mut Meshing)>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    assets: Res<MeshAssets>,
+) {
+    let start = Instant::now();
+    for (entity, &Chunk { chunk }, mut job) in &mut meshing {
+        if start.elapsed() > UPLOAD_BUDGET {
+            break;
+        }
+        if let Some((built, connections)) = check_ready(&mut job.task) {
+            let mut entity = commands.entity(entity);
+            entity.remove::<Meshing>().insert(connections);
+            attach_meshes(&mut entity, chunk, built, &mut meshes, &assets);
+        }
+    }
+}
+
+fn attach_meshes(
+    entity: &mut EntityCommands,
+    chunk: IVec3,
+    built: ChunkMeshes,
+    meshes: &mut Assets<Mesh>,
+    assets: &MeshAssets,
+) {
+    entity.despawn_related::<Children>().insert((
+        Mesh3d(meshes.add(built.opaque)),
+        MeshMaterial3d(assets.material.clone()),
+    ));
+    if let Some(translucent) = built.translucent {
+        entity.with_child((
+            TranslucentPart,
+            Mesh3d(meshes.add(translucent)),
+            MeshMaterial3d(assets.translucent_material.clone()),
+        ));
+    }
+    let meshed = ChunkMeshed {
+        chunk,
+        entity: entity.id(),
+    };
+    entity.commands().send_event(meshed);
+}
+
+/// Sum the sizes of the terrain meshes
+pub fn mesh_stats(
+    meshed: Query<(&Mesh3d, &Visibility), With<MeshMaterial3d<TerrainMaterial>>>,
+    meshes: Res<Assets<Mesh>>,
+    config: Res<MeshingConfig>,
+    mut stats: ResMut<MeshStats>,
+) {
+    *stats = MeshStats {
+        config: *config,
+        ..default()
+    };
+    for (mesh, visibility) in &meshed {
+        // the translucent parts follow their chunk
+        if visibility == Visibility::Hidden {
+            stats.hidden += 1;
+        }
+        let Some(mesh) = meshes.get(mesh) else {
             continue;
         };
-        let mut positions = Vec::new();
-        let mut normals = Vec::new();
-        let mut indices = Vec::new();
-        let mut texture_uvs = Vec::new();
-        let mut texture_indices = Vec::new();
-        for (&local, &block) in &neighborhood.zero.blocks {
-            let Some(textures) = block.textures() else {
-                continue;
+        stats.vertices += mesh.count_vertices();
+        stats.indices += mesh.indices().map_or(0, Indices::len);
+    }
+}
+
+impl Detail {
+    /// Distance to a loader along its metric within which chunks are meshed at full detail,
+    /// each coarser level reaching twice as far
+    const FULL_DISTANCE: f32 = 96.0;
+
+    pub fn at_distance(distance: f32) -> Self {
+        match distance / Self::FULL_DISTANCE {
+            ..1.0 => Detail::Full,
+            ..2.0 => Detail::Half,
+            ..4.0 => Detail::Quarter,
+            _ => Detail::Eighth,
+        }
+    }
+
+    /// How many voxels wide a cell is
+    const fn scale(self) -> i32 {
+        match self {
+            Detail::Full => 1,
+            Detail::Half => 2,
+            Detail::Quarter => 4,
+            Detail::Eighth => 8,
+        }
+    }
+}
+
+impl FaceOwnership {
+    /// The chunks around a chunk its mesh waits on
+    pub fn neighborhood(self) -> NeighborhoodSize {
+        match self {
+            FaceOwnership::Blocks => NeighborhoodSize::Full,
+            FaceOwnership::PositiveBoundaries => NeighborhoodSize::Positive,
+        }
+    }
+
+    /// The chunks around a chunk its mesh reads, those it does not wait on only when loaded
+    pub fn lookups(self) -> NeighborhoodSize {
+        match self {
+            FaceOwnership::Blocks => NeighborhoodSize::Full,
+            // the rails of fences reach toward the negative neighbours
+            FaceOwnership::PositiveBoundaries => NeighborhoodSize::Faces,
+        }
+    }
+}
+
+/// Builds the meshes of a chunk from a copy of its neighbourhood
+struct ChunkMesher<'a> {
+    registry: &'a BlockRegistry,
+    snapshot: &'a Neighborhood<ChunkBlocks>,
+    config: MeshingConfig,
+    opaque: MeshBuffers,
+    translucent: MeshBuffers,
+}
+
+fn build_meshes(
+    registry: &BlockRegistry,
+    snapshot: &Neighborhood<ChunkBlocks>,
+    config: MeshingConfig,
+    detail: Detail,
+) -> ChunkMeshes {
+    if detail != Detail::Full {
+        return build_downsampled_meshes(registry, snapshot, config, detail.scale());
+    }
+    let mut mesher = ChunkMesher {
+        registry,
+        snapshot,
+        config,
+        opaque: default(),
+        translucent: default(),
+    };
+    for (local, block) in snapshot.centre().iter() {
+        assert!(local.x >= 0);
+        assert!(local.x < CHUNK_WIDTH);
+        assert!(local.y >= 0);
+        assert!(local.y < CHUNK_WIDTH);
+        assert!(local.z >= 0);
+        assert!(local.z < CHUNK_WIDTH);
+        mesher.add_block(local, block, None);
+    }
+    if config.ownership == FaceOwnership::PositiveBoundaries {
+        // the blocks of the positive neighbours right behind the boundaries
+        for side in [Side::XPos, Side::YPos, Side::ZPos] {
+            let normal = side.dir();
+            let boundary = Side::from_dir(-normal).unwrap();
+            for local in cuboid(normal * CHUNK_WIDTH, IVec3::splat(CHUNK_WIDTH - 1) + normal) {
+                mesher.add_block(local, snapshot.block(local), Some(boundary));
+            }
+        }
+    }
+    ChunkMeshes::new(mesher.opaque, mesher.translucent, 1)
+}
+
+/// Build the meshes of a far chunk, each cell of voxels drawn as a single cube
+///
+/// The opaque faces on the boundaries of the chunk are kept even against a neighbour,
+/// as skirts covering the gaps with the neighbours meshed at another detail.
+/// Those on the negative boundaries are left to the neighbours there when they own them.
+fn build_downsampled_meshes(
+    registry: &BlockRegistry,
+    snapshot: &Neighborhood<ChunkBlocks>,
+    config: MeshingConfig,
+    scale: i32,
+) -> ChunkMeshes {
+    let width = CHUNK_WIDTH / scale;
+    // the cells of the chunk, and those of the neighbours around it
+    let cells = cuboid(IVec3::NEG_ONE, IVec3::splat(width))
+        .map(|cell| (cell, downsample(registry, snapshot, cell, scale)))
+        .collect::<HashMap<_, _>>();
+    let cell = |at| cells.get(&at).copied().unwrap_or(Block::AIR);
+    let inside = |at: IVec3| at.min_element() >= 0 && at.max_element() < width;
+    let occludes = |at| registry.occludes(cell(at));
+    let half = config.ownership == FaceOwnership::PositiveBoundaries;
+    // the cells of the positive neighbours right behind the boundaries only give the faces on them
+    let behind = [Side::XPos, Side::YPos, Side::ZPos]
+        .into_iter()
+        .filter(|_| half)
+        .flat_map(|side| {
+            let normal = side.dir();
+            let boundary = Side::from_dir(-normal);
+            cuboid(normal * width, IVec3::splat(width - 1) + normal).map(move |at| (at, boundary))
+        });
+    let mut opaque = MeshBuffers::default();
+    let mut translucent = MeshBuffers::default();
+    for (at, boundary) in cuboid(IVec3::ZERO, IVec3::splat(width - 1))
+        .map(|at| (at, None))
+        .chain(behind)
+    {
+        let block = cell(at);
+        let Some(textures) = registry.textures(block) else {
+            continue;
+        };
+        let opacity = registry.properties(block).opacity;
+        let buffers = match opacity {
+            Opacity::Opaque | Opacity::Cutout => &mut opaque,
+            Opacity::Translucent => &mut translucent,
+        };
+        let visible = Sides::from_fn(|side| {
+            let front = at + side.dir();
+            let owned = match boundary {
+                Some(boundary) => side == boundary,
+                None => !(half && front.min_element() < 0),
             };
-            assert!(local.x >= 0);
-            assert!(local.x < CHUNK_WIDTH);
-            assert!(local.y >= 0);
-            assert!(local.y < CHUNK_WIDTH);
-            assert!(local.z >= 0);
-            assert!(local.z < CHUNK_WIDTH);
-            make_cube_mesh(
-                local.as_vec3(),
-                Sides::AXIS.map(|dir| !neighborhood.get(local + dir)),
+            owned
+                && (!registry.face_hidden(block, cell(front))
+                    || (opacity != Opacity::Translucent && !inside(front)))
+        });
+        let occlusion = Sides::from_fn(|side| {
+            if visible[side] {
+                face_occlusion(at, side, occludes)
+            } else {
+                [3; 4]
+            }
+        });
+        if config.mode == MeshingMode::Greedy {
+            for side in Side::ALL.into_iter().filter(|&side| visible[side]) {
+                buffers.merged[side].insert(at, (textures[side], occlusion[side]));
+            }
+        } else {
+            make_box_mesh(
+                at.as_vec3(),
+                BlockBox::FULL,
+                visible,
                 textures,
-                // !neighborhood.get(local + IVec3::X),
-                // !neighborhood.get(local - IVec3::X),
-                // !neighborhood.get(local + IVec3::Y),
-                // !neighborhood.get(local - IVec3::Y),
-                // !neighborhood.get(local + IVec3::Z),
-                // !neighborhood.get(local - IVec3::Z),
-                &mut positions,
-                &mut normals,
-                &mut indices,
-                &mut texture_uvs,
-                &mut texture_indices,
+                occlusion,
+                buffers,
             );
         }
-        assert_eq!(positions.len(), normals.len());
-        assert_eq!(positions.len(), texture_uvs.len());
-        assert_eq!(positions.len(), texture_indices.len());
-        assert_eq!(indices.len() % 6, 0);
-        assert_eq!(positions.len() % 4, 0);
-        assert_eq!(positions.len() / 4, indices.len() / 6);
-        let mesh = Mesh::new(PrimitiveTopology::TriangleList, default())
-            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
-            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
-            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, texture_uvs)
-            .with_inserted_attribute(ATTRIBUTE_TEXTURE_INDEX, texture_indices)
-            .with_inserted_indices(Indices::U32(indices));
-        let mesh = meshes.add(mesh);
-        commands
-            .entity(entity)
-            .remove::<MeshReload>()
-            .insert((Mesh3d(mesh), MeshMaterial3d(assets.material.clone())));
+    }
+    ChunkMeshes::new(opaque, translucent, scale)
+}
+
+/// The block standing for a cell of voxels, air unless full blocks fill at least half of it
+///
+/// The highest of them is chosen, so the surface of the terrain keeps its texture.
+fn downsample(
+    registry: &BlockRegistry,
+    snapshot: &Neighborhood<ChunkBlocks>,
+    cell: IVec3,
+    scale: i32,
+) -> Block {
+    let min = cell * scale;
+    let mut full = 0;
+    let mut top = None;
+    for at in cuboid(min, min + IVec3::splat(scale - 1)) {
+        // a cell never spans two chunks
+        let Some(block) = snapshot.try_block(at) else {
+            return Block::AIR;
+        };
+        if !block.is_air() && registry.get(block).shape.is_full() {
+            full += 1;
+            if top.is_none_or(|(y, _)| at.y > y) {
+                top = Some((at.y, block));
+            }
+        }
+    }
+    match top {
+        Some((_, block)) if full * 2 >= scale.pow(3) => block,
+        _ => Block::AIR,
+    }
+}
+
+impl ChunkMeshes {
+    /// Merge the collected faces, cells being `scale` voxels wide
+    fn new(mut opaque: MeshBuffers, mut translucent: MeshBuffers, scale: i32) -> Self {
+        for buffers in [&mut opaque, &mut translucent] {
+            buffers.make_merged_faces();
+            for position in &mut buffers.positions {
+                *position = (Vec3::from(*position) * scale as f32).to_array();
+            }
+        }
+        ChunkMeshes {
+            opaque: opaque.into_mesh(),
+            translucent: (!translucent.indices.is_empty()).then(|| translucent.into_mesh()),
+        }
+    }
+}
+
+impl ChunkMesher<'_> {
+    /// Add the faces of a block that the chunk owns
+    ///
+    /// A block of a neighbour only gives its faces lying on the `boundary` with the chunk.
+    fn add_block(&mut self, local: IVec3, block: Block, boundary: Option<Side>) {
+        let (registry, snapshot) = (self.registry, self.snapshot);
+        let Some(textures) = registry.textures(block) else {
+            return;
+        };
+        let buffers = match registry.properties(block).opacity {
+            Opacity::Opaque | Opacity::Cutout => &mut self.opaque,
+            Opacity::Translucent => &mut self.translucent,
+        };
+        if registry.get(block).shape == Shape::Cross {
+            if boundary.is_none() {
+                make_cross_mesh(local.as_vec3(), textures.x_pos.0, buffers);
+            }
+            return;
+        }
+        let half = self.config.ownership == FaceOwnership::PositiveBoundaries;
+        let neighbour = |side: Side| snapshot.try_block(local + side.dir());
+        let hidden = Sides::from_fn(|side| {
+            neighbour(side).is_some_and(|neighbour| registry.face_hidden(block, neighbour))
+        });
+        let occludes = |at| registry.occludes(snapshot.block(at));
+        for part in registry.boxes(block, neighbour) {
+            let owned = Sides::from_fn(|side| match boundary {
+                Some(boundary) => side == boundary && part.touches(side),
+                // the faces on the negative boundaries of the chunk belong to the neighbours there
+                None => !(half && part.touches(side) && (local + side.dir()).min_element() < 0),
+            });
+            // only the faces lying against a neighbour can be hidden by it
+            let visible =
+                Sides::from_fn(|side| owned[side] && !(hidden[side] && part.touches(side)));
+            // and only the faces covering a whole side of the voxel are shaded by the blocks around
+            let occlusion = Sides::from_fn(|side| {
+                if !half && visible[side] && part == BlockBox::FULL {
+                    face_occlusion(local, side, occludes)
+                } else {
+                    [3; 4]
+                }
+            });
+            if self.config.mode == MeshingMode::Greedy && part == BlockBox::FULL {
+                for side in Side::ALL.into_iter().filter(|&side| visible[side]) {
+                    buffers.merged[side].insert(local, (textures[side], occlusion[side]));
+                }
+            } else {
+                make_box_mesh(local.as_vec3(), part, visible, textures, occlusion, buffers);
+            }
+        }
+    }
+}
+
+impl MeshBuffers {
+    /// Merge the collected faces of each side into as few quads as possible,
+    /// the texture repeating across each quad
+    fn make_merged_faces(&mut self) {
+        for side in Side::ALL {
+            let mut faces = std::mem::take(&mut self.merged[side]);
+            let normal = side.dir().abs();
+            // the two directions spanning the plane of the faces
+            let (u, v) = (normal.yzx(), normal.zxy());
+            // quads grow from their lowest corner
+            let mut starts = faces.keys().copied().collect::<Vec<_>>();
+            starts.sort_unstable_by_key(|&at| (at.dot(normal), at.dot(v), at.dot(u)));
+            for start in starts {
+                let Some(&(texture, occlusion)) = faces.get(&start) else {
+                    continue;
+                };
+                let same =
+                    |faces: &HashMap<_, _>, at| faces.get(&at) == Some(&(texture, occlusion));
+                let mut width = 1;
+                while same(&faces, start + u * width) {
+                    width += 1;
+                }
+                let mut height = 1;
+                while (0..width).all(|i| same(&faces, start + u * i + v * height)) {
+                    height += 1;
+                }
+                for j in 0..height {
+                    for i in 0..width {
+                        faces.remove(&(start + u * i + v * j));
+                    }
+                }
+                let quad = BlockBox::new(Vec3::ZERO, (normal + u * width + v * height).as_vec3());
+                let visible = Sides::from_fn(|other| other == side);
+                make_box_mesh(
+                    start.as_vec3(),
+                    quad,
+                    visible,
+                    Sides::from_fn(|_| texture),
+                    Sides::from_fn(|_| occlusion),
+                    self,
+                );
+            }
+        }
+    }
+
+    fn into_mesh(self) -> Mesh {
+        assert_eq!(self.positions.len(), self.normals.len());
+        assert_eq!(self.positions.len(), self.texture_uvs.len());
+        assert_eq!(self.positions.len(), self.texture_indices.len());
+        assert_eq!(self.positions.len(), self.ambient_occlusion.len());
+        assert_eq!(self.indices.len() % 6, 0);
+        assert_eq!(self.positions.len() % 4, 0);
+        assert_eq!(self.positions.len() / 4, self.indices.len() / 6);
+        Mesh::new(PrimitiveTopology::TriangleList, default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.positions)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, self.texture_uvs)
+            .with_inserted_attribute(ATTRIBUTE_TEXTURE_INDEX, self.texture_indices)
+            .with_inserted_attribute(ATTRIBUTE_AMBIENT_OCCLUSION, self.ambient_occlusion)
+            .with_inserted_indices(Indices::U32(self.indices))
     }
 }
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
@@ -120,11 +638,24 @@ pub struct TerrainMaterial {
     #[texture(0, dimension = "2d_array")]
     #[sampler(1)]
     texture: Handle<Image>,
+    /// Texels less opaque than this are discarded
+    #[uniform(2)]
+    alpha_cutoff: f32,
+    alpha_mode: AlphaMode,
 }
 const ATTRIBUTE_TEXTURE_INDEX: MeshVertexAttribute =
     MeshVertexAttribute::new("TextureIndex", 2760892297209218923, VertexFormat::Uint32);
+/// How much light reaches the vertex, from 0 when fully occluded to 1
+const ATTRIBUTE_AMBIENT_OCCLUSION: MeshVertexAttribute = MeshVertexAttribute::new(
+    "AmbientOcclusion",
+    4189337460934752087,
+    VertexFormat::Float32,
+);
 
 impl Material for TerrainMaterial {
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
     fn fragment_shader() -> ShaderRef {
         "shaders/terrain_material.wgsl".into()
     }
@@ -142,87 +673,174 @@ impl Material for TerrainMaterial {
             Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
             ATTRIBUTE_TEXTURE_INDEX.at_shader_location(2),
             Mesh::ATTRIBUTE_NORMAL.at_shader_location(3),
+            ATTRIBUTE_AMBIENT_OCCLUSION.at_shader_location(4),
         ])?;
         descriptor.vertex.buffers = Vec::from([vertex_layout]);
         Ok(())
     }
 }
 
-fn make_cube_mesh(
+/// Corners of each face of the unit cube, in vertex order
+const FACE_CORNERS: Sides<[[f32; 3]; 4]> = Sides {
+    x_pos: [
+        [1.0, 0.0, 0.0],
+        [1.0, 0.0, 1.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 1.0, 1.0],
+    ],
+    x_neg: [
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [0.0, 1.0, 0.0],
+        [0.0, 1.0, 1.0],
+    ],
+    y_pos: [
+        [0.0, 1.0, 0.0],
+        [0.0, 1.0, 1.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 1.0, 1.0],
+    ],
+    y_neg: [
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 0.0, 1.0],
+    ],
+    z_pos: [
+        [0.0, 0.0, 1.0],
+        [0.0, 1.0, 1.0],
+        [1.0, 0.0, 1.0],
+        [1.0, 1.0, 1.0],
+    ],
+    z_neg: [
+        [0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+    ],
+};
+/// Triangles of each face, split along the diagonal between the second and third corners
+const FACE_TRIANGLES: Sides<[u32; 6]> = Sides {
+    x_pos: [0, 2, 1, 1, 2, 3],
+    x_neg: [0, 1, 2, 1, 3, 2],
+    y_pos: [0, 1, 2, 1, 3, 2],
+    y_neg: [0, 2, 1, 1, 2, 3],
+    z_pos: [0, 2, 1, 1, 2, 3],
+    z_neg: [0, 1, 2, 1, 3, 2],
+};
+/// The same triangles split along the diagonal between the first and last corners
+const FACE_TRIANGLES_FLIPPED: Sides<[u32; 6]> = Sides {
+    x_pos: [0, 2, 3, 0, 3, 1],
+    x_neg: [0, 1, 3, 0, 3, 2],
+    y_pos: [0, 1, 3, 0, 3, 2],
+    y_neg: [0, 2, 3, 0, 3, 1],
+    z_pos: [0, 2, 3, 0, 3, 1],
+    z_neg: [0, 1, 3, 0, 3, 2],
+};
+
+/// Light reaching each corner of a face of a full block, from 0 to 3 when nothing occludes it
+///
+/// A corner is darkened by the blocks in front of the face along its two edges and diagonally,
+/// and fully occluded when both edges are.
+fn face_occlusion(local: IVec3, side: Side, occludes: impl Fn(IVec3) -> bool) -> [u8; 4] {
+    let normal = side.dir().abs();
+    let front = local + side.dir();
+    FACE_CORNERS[side].map(|corner| {
+        // toward the corner, along the two directions spanning the face
+        let toward = (Vec3::from(corner) * 2.0 - 1.0).as_ivec3();
+        let (u, v) = (toward * normal.yzx(), toward * normal.zxy());
+        let (edge_u, edge_v) = (occludes(front + u), occludes(front + v));
+        if edge_u && edge_v {
+            0
+        } else {
+            3 - edge_u as u8 - edge_v as u8 - occludes(front + u + v) as u8
+        }
+    })
+}
+
+/// Add the visible faces of a cube, each with a texture rotated by a number of quarter turns,
+/// and shaded by the light reaching its corners
+fn make_box_mesh(
     tr: Vec3,
+    part: BlockBox,
     visible: Sides<bool>,
-    texture: Sides<u32>,
-    positions: &mut Vec<[f32; 3]>,
-    normals: &mut Vec<[f32; 3]>,
-    indices: &mut Vec<u32>,
-    texture_uvs: &mut Vec<[f32; 2]>,
-    texture_indices: &mut Vec<u32>,
+    texture: Sides<(u32, usize)>,
+    occlusion: Sides<[u8; 4]>,
+    buffers: &mut MeshBuffers,
 ) {
-    let position = Sides {
-        x_pos: [
-            [1.0, 0.0, 0.0],
-            [1.0, 0.0, 1.0],
-            [1.0, 1.0, 0.0],
-            [1.0, 1.0, 1.0],
-        ],
-        x_neg: [
+    let position = FACE_CORNERS
+        .map(|face| face.map(|corner| part.min + Vec3::from(corner) * (part.max - part.min)));
+    let normal: Sides<[f32; 3]> = Sides::AXIS.map(|v| v.as_vec3().into());
+    // the texture is cropped to the part of the face the box covers
+    let uv = |side, local: Vec3| match side {
+        Side::XPos | Side::XNeg => [local.z, 1.0 - local.y],
+        Side::YPos | Side::YNeg => [local.x, local.z],
+        Side::ZPos | Side::ZNeg => [1.0 - local.x, 1.0 - local.y],
+    };
+    for side in Side::ALL {
+        if visible[side] {
+            let index = buffers.positions.len() as u32;
+            buffers
+                .positions
+                .extend(position[side].map(|local| (tr + local).to_array()));
+            buffers.normals.extend([normal[side]; 4]);
+            let light = occlusion[side];
+            // split along the brighter diagonal, so the shading is interpolated symmetrically
+            let triangles = if light[0] + light[3] > light[1] + light[2] {
+                FACE_TRIANGLES_FLIPPED
+            } else {
+                FACE_TRIANGLES
+            };
+            buffers
+                .indices
+                .extend(triangles[side].map(|vertex| index + vertex));
+            buffers
+                .ambient_occlusion
+                .extend(light.map(|light| light as f32 / 3.0));
+            let (texture, turns) = texture[side];
+            buffers.texture_uvs.extend(position[side].map(|local| {
+                let mut uv = uv(side, local);
+                for _ in 0..turns {
+                    uv = [uv[1], 1.0 - uv[0]];
+                }
+                uv
+            }));
+            buffers.texture_indices.extend([texture; 4]);
+        }
+    }
+}
+
+/// Add two double sided planes crossing along the diagonals of the voxel
+fn make_cross_mesh(tr: Vec3, texture: u32, buffers: &mut MeshBuffers) {
+    let planes = [
+        [
             [0.0, 0.0, 0.0],
-            [0.0, 0.0, 1.0],
             [0.0, 1.0, 0.0],
-            [0.0, 1.0, 1.0],
-        ],
-        y_pos: [
-            [0.0, 1.0, 0.0],
-            [0.0, 1.0, 1.0],
-            [1.0, 1.0, 0.0],
-            [1.0, 1.0, 1.0],
-        ],
-        y_neg: [
-            [0.0, 0.0, 0.0],
-            [0.0, 0.0, 1.0],
-            [1.0, 0.0, 0.0],
             [1.0, 0.0, 1.0],
+            [1.0, 1.0, 1.0],
         ],
-        z_pos: [
+        [
             [0.0, 0.0, 1.0],
             [0.0, 1.0, 1.0],
-            [1.0, 0.0, 1.0],
-            [1.0, 1.0, 1.0],
-        ],
-        z_neg: [
-            [0.0, 0.0, 0.0],
-            [0.0, 1.0, 0.0],
             [1.0, 0.0, 0.0],
             [1.0, 1.0, 0.0],
         ],
-    }
-    .map(|block| block.map(|[x, y, z]| [x + tr.x, y + tr.y, z + tr.z]));
-    let normal: Sides<[f32; 3]> = Sides::AXIS.map(|v| v.as_vec3().into());
-    let uv = Sides {
-        x_pos: [[0.0, 1.0], [1.0, 1.0], [0.0, 0.0], [1.0, 0.0]],
-        x_neg: [[0.0, 1.0], [1.0, 1.0], [0.0, 0.0], [1.0, 0.0]],
-        y_pos: [[0.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]],
-        y_neg: [[0.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]],
-        z_pos: [[1.0, 1.0], [1.0, 0.0], [0.0, 1.0], [0.0, 0.0]],
-        z_neg: [[1.0, 1.0], [1.0, 0.0], [0.0, 1.0], [0.0, 0.0]],
-    };
-    let vertex = Sides {
-        x_pos: [0, 2, 1, 1, 2, 3],
-        x_neg: [0, 1, 2, 1, 3, 2],
-        y_pos: [0, 1, 2, 1, 3, 2],
-        y_neg: [0, 2, 1, 1, 2, 3],
-        z_pos: [0, 2, 1, 1, 2, 3],
-        z_neg: [0, 1, 2, 1, 3, 2],
-    };
-
-    for side in Side::ALL {
-        if visible[side] {
-            let index = positions.len() as u32;
-            positions.extend(position[side]);
-            normals.extend([normal[side]; 4]);
-            indices.extend(vertex[side].map(|vertex| index + vertex));
-            texture_uvs.extend(uv[side]);
-            texture_indices.extend([texture[side]; 4]);
+    ];
+    let uv = [[0.0, 1.0], [0.0, 0.0], [1.0, 1.0], [1.0, 0.0]];
+    for plane in planes {
+        let [a, b, c, _] = plane.map(Vec3::from);
+        let normal = (b - a).cross(c - a).normalize();
+        // one face on each side of the plane
+        for (normal, vertex) in [(normal, [0, 1, 2, 1, 3, 2]), (-normal, [0, 2, 1, 1, 2, 3])] {
+            let index = buffers.positions.len() as u32;
+            buffers
+                .positions
+                .extend(plane.map(|local| (tr + Vec3::from(local)).to_array()));
+            buffers.normals.extend([normal.to_array(); 4]);
+            buffers.indices.extend(vertex.map(|vertex| index + vertex));
+            buffers.texture_uvs.extend(uv);
+            buffers.texture_indices.extend([texture; 4]);
+            buffers.ambient_occlusion.extend([1.0; 4]);
         }
     }
 }
@@ -0,0 +1,141 @@
+use arrayvec::ArrayVec;
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    spacial::Side,
+    terrain::{
+        BlockState,
+        state::{Half, quarter_turns},
+    },
+};
+
+/// The geometry of a block inside its voxel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    #[default]
+    Cube,
+    /// The half of the voxel given by the state
+    Slab,
+    /// A slab with a quarter of the voxel on top, at the back of the front given by the state
+    Stairs,
+    /// A post with rails toward the connected neighbours
+    Fence,
+    /// Two crossed planes along the diagonals, for plants
+    Cross,
+}
+
+/// An axis aligned box, in voxel coordinates when part of a shape
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+/// Enough for a fence post with two rails on each side
+pub type Boxes = ArrayVec<BlockBox, 9>;
+
+impl BlockBox {
+    pub const FULL: Self = Self::new(Vec3::ZERO, Vec3::ONE);
+
+    pub const fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Whether a face of the box lies on the corresponding face of the voxel
+    pub fn touches(self, side: Side) -> bool {
+        match side {
+            Side::XPos => self.max.x == 1.0,
+            Side::XNeg => self.min.x == 0.0,
+            Side::YPos => self.max.y == 1.0,
+            Side::YNeg => self.min.y == 0.0,
+            Side::ZPos => self.max.z == 1.0,
+            Side::ZNeg => self.min.z == 0.0,
+        }
+    }
+
+    pub fn translated(self, offset: Vec3) -> Self {
+        Self::new(self.min + offset, self.max + offset)
+    }
+
+    /// Quarter turns around the vertical axis passing through the center of the voxel,
+    /// each one bringing `ZPos` to `XPos`
+    fn turned(self, turns: usize) -> Self {
+        let center = Vec3::new(0.5, 0.0, 0.5);
+        let turn = |point: Vec3| {
+            let mut point = point - center;
+            for _ in 0..turns {
+                point = Vec3::new(point.z, point.y, -point.x);
+            }
+            point + center
+        };
+        let (a, b) = (turn(self.min), turn(self.max));
+        Self::new(a.min(b), a.max(b))
+    }
+}
+
+/// Sixteenths of a voxel, the resolution of the textures
+const fn px(min: [f32; 3], max: [f32; 3]) -> BlockBox {
+    BlockBox::new(
+        Vec3::new(min[0] / 16.0, min[1] / 16.0, min[2] / 16.0),
+        Vec3::new(max[0] / 16.0, max[1] / 16.0, max[2] / 16.0),
+    )
+}
+
+const BOTTOM_SLAB: BlockBox = px([0.0, 0.0, 0.0], [16.0, 8.0, 16.0]);
+const TOP_SLAB: BlockBox = px([0.0, 8.0, 0.0], [16.0, 16.0, 16.0]);
+const FENCE_POST: BlockBox = px([6.0, 0.0, 6.0], [10.0, 16.0, 10.0]);
+/// The rails going from the post toward `ZPos`
+const FENCE_RAILS: [BlockBox; 2] = [
+    px([7.0, 6.0, 10.0], [9.0, 9.0, 16.0]),
+    px([7.0, 12.0, 10.0], [9.0, 15.0, 16.0]),
+];
+/// What is outlined of crossed planes, which have no box
+const CROSS_BOUNDS: BlockBox = px([2.0, 0.0, 2.0], [14.0, 14.0, 14.0]);
+
+impl Shape {
+    /// Whether the shape fills its voxel, only such shapes hide the faces of their neighbours
+    pub fn is_full(self) -> bool {
+        self == Shape::Cube
+    }
+
+    /// The boxes the shape is made of, drawn and collided with
+    ///
+    /// `connected` tells whether a fence continues toward a horizontal side.
+    pub fn boxes(self, state: BlockState, connected: impl Fn(Side) -> bool) -> Boxes {
+        let (slab, upper) = match state.half {
+            Half::Bottom => (BOTTOM_SLAB, TOP_SLAB),
+            Half::Top => (TOP_SLAB, BOTTOM_SLAB),
+        };
+        let mut boxes = Boxes::new();
+        match self {
+            Shape::Cube => boxes.push(BlockBox::FULL),
+            Shape::Slab => boxes.push(slab),
+            Shape::Stairs => {
+                boxes.push(slab);
+                // the back quarter, away from the front
+                let back = BlockBox::new(upper.min, upper.max.with_z(0.5));
+                boxes.push(back.turned(quarter_turns(state.facing)));
+            }
+            Shape::Fence => {
+                boxes.push(FENCE_POST);
+                for side in [Side::ZPos, Side::XPos, Side::ZNeg, Side::XNeg] {
+                    if connected(side) {
+                        boxes.extend(FENCE_RAILS.map(|rail| rail.turned(quarter_turns(side))));
+                    }
+                }
+            }
+            Shape::Cross => {}
+        }
+        boxes
+    }
+
+    /// The boxes outlining the shape when pointed at
+    pub fn outline(self, state: BlockState, connected: impl Fn(Side) -> bool) -> Boxes {
+        match self {
+            Shape::Cross => Boxes::from_iter([CROSS_BOUNDS]),
+            _ => self.boxes(state, connected),
+        }
+    }
+}
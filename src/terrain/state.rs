@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::spacial::{Side, Sides};
+
+/// Per-voxel data completing a block type, such as its orientation
+///
+/// Its meaning depends on the `StateKind` of the block,
+/// blocks without state always have the default one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockState {
+    /// Where the top of the model is turned for `Axis` blocks,
+    /// where its front (`ZPos`) is turned for `Horizontal` ones
+    pub facing: Side,
+    pub half: Half,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Half {
+    Bottom,
+    Top,
+}
+
+/// The state a block type carries, declared in the registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StateKind {
+    #[default]
+    None,
+    /// Lies along the axis of the face it is placed against, like logs
+    Axis,
+    /// Turns its front toward the player, like furnaces
+    Horizontal,
+    /// Fills the bottom or top half of the voxel, like slabs
+    Half,
+    /// Both, like stairs
+    HorizontalHalf,
+}
+
+/// Sides in the order of a quarter turn around Y
+const HORIZONTAL: [Side; 4] = [Side::ZPos, Side::XPos, Side::ZNeg, Side::XNeg];
+/// Sides in the order of their encoding, the default one coming first
+const ENCODING: [Side; 6] = [
+    Side::YPos,
+    Side::YNeg,
+    Side::XPos,
+    Side::XNeg,
+    Side::ZPos,
+    Side::ZNeg,
+];
+
+impl Default for BlockState {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl BlockState {
+    pub const DEFAULT: Self = Self {
+        facing: Side::YPos,
+        half: Half::Bottom,
+    };
+
+    pub fn to_bits(self) -> u8 {
+        let facing = ENCODING
+            .iter()
+            .position(|&side| side == self.facing)
+            .unwrap() as u8;
+        facing | (self.half as u8) << 3
+    }
+
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        if bits >> 4 != 0 {
+            return None;
+        }
+        Some(Self {
+            facing: *ENCODING.get((bits & 0b111) as usize)?,
+            half: if bits & 0b1000 == 0 {
+                Half::Bottom
+            } else {
+                Half::Top
+            },
+        })
+    }
+
+    /// Apply a transformation of the space to the orientation
+    pub fn transformed(self, f: impl Fn(IVec3) -> IVec3) -> Self {
+        Self {
+            facing: Side::from_dir(f(self.facing.dir())).unwrap(),
+            ..self
+        }
+    }
+}
+
+/// Number of quarter turns around Y bringing `ZPos` to `side`
+pub fn quarter_turns(side: Side) -> usize {
+    HORIZONTAL.iter().position(|&h| h == side).unwrap_or(0)
+}
+
+impl StateKind {
+    /// The state of a block placed against a face
+    ///
+    /// `dir` is the direction in which the ray entered the block it hit,
+    /// `point` where it hit it, and `ray` the direction the player looks to.
+    pub fn placed(self, dir: IVec3, point: Vec3, ray: Vec3) -> BlockState {
+        // the front faces the player, looking toward the face if it is a side one
+        let toward = if dir.y == 0 {
+            -dir
+        } else if ray.x.abs() > ray.z.abs() {
+            IVec3::NEG_X * ray.x.signum() as i32
+        } else {
+            IVec3::NEG_Z * ray.z.signum() as i32
+        };
+        let horizontal = Side::from_dir(toward).unwrap();
+        // against a side face, the half follows where the face is hit
+        let half = match dir.y {
+            -1 => Half::Bottom,
+            1 => Half::Top,
+            _ if point.y.rem_euclid(1.0) > 0.5 => Half::Top,
+            _ => Half::Bottom,
+        };
+        match self {
+            Self::None => BlockState::DEFAULT,
+            Self::Axis => BlockState {
+                facing: Side::from_dir(dir.abs()).unwrap(),
+                ..BlockState::DEFAULT
+            },
+            Self::Horizontal => BlockState {
+                facing: horizontal,
+                ..BlockState::DEFAULT
+            },
+            Self::Half => BlockState {
+                half,
+                ..BlockState::DEFAULT
+            },
+            Self::HorizontalHalf => BlockState {
+                facing: horizontal,
+                half,
+            },
+        }
+    }
+
+    /// For each side of the voxel, which side of the model shows there,
+    /// and by how many quarter turns its texture is rotated
+    pub fn orient(self, state: BlockState) -> Sides<(Side, usize)> {
+        use Side::*;
+        Sides::from_fn(|side| match (self, state.facing, side) {
+            (Self::Axis, XPos | XNeg, XPos) => (YPos, 0),
+            (Self::Axis, XPos | XNeg, XNeg) => (YNeg, 0),
+            (Self::Axis, XPos | XNeg, YPos) => (XNeg, 1),
+            (Self::Axis, XPos | XNeg, YNeg) => (XPos, 1),
+            (Self::Axis, XPos | XNeg, ZPos | ZNeg) => (side, 1),
+            (Self::Axis, ZPos | ZNeg, ZPos) => (YPos, 0),
+            (Self::Axis, ZPos | ZNeg, ZNeg) => (YNeg, 0),
+            (Self::Axis, ZPos | ZNeg, YPos) => (ZNeg, 0),
+            (Self::Axis, ZPos | ZNeg, YNeg) => (ZPos, 0),
+            (Self::Axis, ZPos | ZNeg, XPos | XNeg) => (side, 1),
+            (Self::Horizontal | Self::HorizontalHalf, facing, YPos | YNeg) => {
+                (side, quarter_turns(facing))
+            }
+            (Self::Horizontal | Self::HorizontalHalf, facing, side) => {
+                let turns = 4 + quarter_turns(side) - quarter_turns(facing);
+                (HORIZONTAL[turns % 4], 0)
+            }
+            _ => (side, 0),
+        })
+    }
+}
@@ -0,0 +1,287 @@
+use bevy::math::IVec3;
+
+use crate::terrain::{Block, CHUNK_WIDTH};
+
+const VOLUME: usize = (CHUNK_WIDTH * CHUNK_WIDTH * CHUNK_WIDTH) as usize;
+
+/// Dense storage of the blocks of a chunk
+///
+/// Chunks made of a single block type (all air, all stone) take no space at all.
+/// Other chunks store for each voxel an index into a palette of the distinct blocks
+/// present in the chunk, bit-packed with as few bits as the palette allows.
+#[derive(Debug, Clone)]
+pub enum BlockStorage {
+    Uniform(Block),
+    Paletted(Paletted),
+}
+
+#[derive(Debug, Clone)]
+pub struct Paletted {
+    /// Distinct blocks, an entry with a zero count is free to reuse
+    palette: Vec<Block>,
+    /// How many voxels use each palette entry
+    counts: Vec<u32>,
+    /// Bits per index, always a power of two so indices never straddle words
+    bits: u32,
+    words: Vec<u64>,
+}
+
+impl Default for BlockStorage {
+    fn default() -> Self {
+        Self::Uniform(Block::AIR)
+    }
+}
+
+fn linearize(local: IVec3) -> usize {
+    debug_assert!(local.cmpge(IVec3::ZERO).all());
+    debug_assert!(local.cmplt(IVec3::splat(CHUNK_WIDTH)).all());
+    ((local.y * CHUNK_WIDTH + local.z) * CHUNK_WIDTH + local.x) as usize
+}
+
+fn delinearize(index: usize) -> IVec3 {
+    let index = index as i32;
+    IVec3 {
+        x: index % CHUNK_WIDTH,
+        z: index / CHUNK_WIDTH % CHUNK_WIDTH,
+        y: index / (CHUNK_WIDTH * CHUNK_WIDTH),
+    }
+}
+
+impl BlockStorage {
+    pub fn get(&self, local: IVec3) -> Block {
+        match self {
+            Self::Uniform(block) => *block,
+            Self::Paletted(paletted) => paletted.palette[paletted.index(linearize(local))],
+        }
+    }
+
+    /// Replace the block at the given position, returns the previous one
+    pub fn set(&mut self, local: IVec3, block: Block) -> Block {
+        match self {
+            Self::Uniform(previous) if *previous == block => block,
+            Self::Uniform(previous) => {
+                let previous = *previous;
+                let mut paletted = Paletted::uniform(previous);
+                paletted.set(linearize(local), block);
+                *self = Self::Paletted(paletted);
+                previous
+            }
+            Self::Paletted(paletted) => {
+                let previous = paletted.set(linearize(local), block);
+                if let Some(single) = paletted.single() {
+                    *self = Self::Uniform(single);
+                }
+                previous
+            }
+        }
+    }
+
+    /// Iterates over all the non-air blocks with their local position
+    pub fn iter(&self) -> impl Iterator<Item = (IVec3, Block)> + '_ {
+        let (uniform, paletted) = match self {
+            Self::Uniform(Block::AIR) => (None, None),
+            Self::Uniform(block) => (Some(*block), None),
+            Self::Paletted(paletted) => (None, Some(paletted)),
+        };
+        let uniform = uniform
+            .into_iter()
+            .flat_map(|block| (0..VOLUME).map(move |i| (delinearize(i), block)));
+        let paletted = paletted.into_iter().flat_map(|paletted| {
+            (0..VOLUME).filter_map(|i| {
+                let block = paletted.palette[paletted.index(i)];
+                (block != Block::AIR).then(|| (delinearize(i), block))
+            })
+        });
+        uniform.chain(paletted)
+    }
+}
+
+impl Paletted {
+    fn uniform(block: Block) -> Self {
+        Self {
+            palette: Vec::from([block]),
+            counts: Vec::from([VOLUME as u32]),
+            bits: 0,
+            words: Vec::new(),
+        }
+    }
+
+    const fn per_word(bits: u32) -> usize {
+        (u64::BITS / bits) as usize
+    }
+
+    fn index(&self, i: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = Self::per_word(self.bits);
+        let word = self.words[i / per_word];
+        let shift = (i % per_word) as u32 * self.bits;
+        ((word >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn write_index(&mut self, i: usize, index: usize) {
+        let per_word = Self::per_word(self.bits);
+        let word = &mut self.words[i / per_word];
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = ((1 << self.bits) - 1) << shift;
+        *word = (*word & !mask) | ((index as u64) << shift);
+    }
+
+    /// Repack indices so that the palette can hold at least `len` entries
+    fn reserve(&mut self, len: usize) {
+        if len <= 1 << self.bits {
+            return;
+        }
+        let mut bits = self.bits.max(1);
+        while len > 1 << bits {
+            bits *= 2;
+        }
+        let mut packed = Self {
+            palette: Vec::new(),
+            counts: Vec::new(),
+            bits,
+            words: vec![0; VOLUME.div_ceil(Self::per_word(bits))],
+        };
+        for i in 0..VOLUME {
+            packed.write_index(i, self.index(i));
+        }
+        self.bits = bits;
+        self.words = packed.words;
+    }
+
+    fn set(&mut self, i: usize, block: Block) -> Block {
+        let old = self.index(i);
+        let previous = self.palette[old];
+        if previous == block {
+            return block;
+        }
+        let new = match self.palette.iter().position(|&entry| entry == block) {
+            Some(new) => new,
+            None => match self.counts.iter().position(|&count| count == 0) {
+                Some(free) => {
+                    self.palette[free] = block;
+                    free
+                }
+                None => {
+                    self.reserve(self.palette.len() + 1);
+                    self.palette.push(block);
+                    self.counts.push(0);
+                    self.palette.len() - 1
+                }
+            },
+        };
+        self.counts[old] -= 1;
+        self.counts[new] += 1;
+        self.write_index(i, new);
+        previous
+    }
+
+    /// The block filling the whole chunk, if there is only one
+    fn single(&self) -> Option<Block> {
+        let index = self
+            .counts
+            .iter()
+            .position(|&count| count == VOLUME as u32)?;
+        Some(self.palette[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u16) -> Block {
+        Block::from_id(id)
+    }
+
+    fn is_uniform(storage: &BlockStorage, block: Block) -> bool {
+        matches!(storage, BlockStorage::Uniform(uniform) if *uniform == block)
+    }
+
+    fn paletted(storage: &BlockStorage) -> &Paletted {
+        let BlockStorage::Paletted(paletted) = storage else {
+            panic!("several blocks are stored in a palette");
+        };
+        paletted
+    }
+
+    fn assert_blocks(storage: &BlockStorage, expected: &[Block]) {
+        for (i, &block) in expected.iter().enumerate() {
+            assert_eq!(storage.get(delinearize(i)), block);
+        }
+    }
+
+    #[test]
+    fn round_trip_across_palette_resizes() {
+        let mut storage = BlockStorage::default();
+        let mut expected = vec![Block::AIR; VOLUME];
+        // each new block grows the palette, from 1 bit per index up to 16
+        for id in 1..300 {
+            for i in (id as usize..VOLUME).step_by(307) {
+                assert_eq!(storage.set(delinearize(i), block(id)), expected[i]);
+                expected[i] = block(id);
+            }
+            assert_blocks(&storage, &expected);
+        }
+        assert_eq!(paletted(&storage).bits, 16);
+    }
+
+    #[test]
+    fn reads_and_writes_at_word_boundaries() {
+        // the smallest palettes packed with each width
+        for (len, bits) in [(2, 1), (3, 2), (5, 4), (17, 8), (257, 16)] {
+            let mut storage = BlockStorage::default();
+            let mut expected = vec![Block::AIR; VOLUME];
+            let per_word = Paletted::per_word(bits);
+            for id in 1..len {
+                // the last index of a word and the first of the next one
+                for i in [id * per_word - 1, id * per_word] {
+                    storage.set(delinearize(i), block(id as u16));
+                    expected[i] = block(id as u16);
+                }
+            }
+            assert_eq!(paletted(&storage).bits, bits);
+            assert_blocks(&storage, &expected);
+        }
+    }
+
+    #[test]
+    fn uniform_to_paletted_to_uniform() {
+        let stone = block(1);
+        let mut storage = BlockStorage::default();
+        assert!(is_uniform(&storage, Block::AIR));
+
+        let at = IVec3::new(3, 7, 11);
+        assert_eq!(storage.set(at, stone), Block::AIR);
+        assert!(matches!(storage, BlockStorage::Paletted(_)));
+        assert_eq!(storage.get(at), stone);
+        assert_eq!(storage.iter().collect::<Vec<_>>(), [(at, stone)]);
+
+        assert_eq!(storage.set(at, Block::AIR), stone);
+        assert!(is_uniform(&storage, Block::AIR));
+        assert_eq!(storage.iter().count(), 0);
+
+        // filling the chunk with another block makes it uniform again
+        for i in 0..VOLUME {
+            storage.set(delinearize(i), stone);
+        }
+        assert!(is_uniform(&storage, stone));
+        assert_eq!(storage.get(at), stone);
+    }
+
+    #[test]
+    fn freed_palette_entries_are_reused() {
+        let mut storage = BlockStorage::default();
+        storage.set(IVec3::ZERO, block(1));
+        storage.set(IVec3::X, block(2));
+        storage.set(IVec3::ZERO, Block::AIR);
+        storage.set(IVec3::Y, block(3));
+        let paletted = paletted(&storage);
+        assert_eq!(paletted.palette.len(), 3);
+        assert_eq!(paletted.bits, 2);
+        assert_eq!(storage.get(IVec3::ZERO), Block::AIR);
+        assert_eq!(storage.get(IVec3::X), block(2));
+        assert_eq!(storage.get(IVec3::Y), block(3));
+    }
+}
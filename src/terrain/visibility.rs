@@ -0,0 +1,116 @@
+use bevy::{platform::collections::HashSet, prelude::*};
+use std::collections::VecDeque;
+
+use crate::{
+    spacial::{Side, cuboid},
+    terrain::{
+        BlockRegistry, CHUNK_WIDTH, Chunk, ChunkBlocks, ChunksIndex, TerrainLoader, global_to_local,
+    },
+};
+
+/// Which faces of a chunk can be seen from one another through it,
+/// joined by a path of voxels that are not opaque
+///
+/// Found by the generation and meshing jobs, along with their results.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct FaceConnections(u64);
+
+impl FaceConnections {
+    /// Every face seen from every other, for the chunks whose blocks are not known yet
+    const ALL: Self = Self(u64::MAX);
+
+    /// Connect each pair of faces reached by a region
+    fn connect(&mut self, reached: u8) {
+        for from in Side::ALL
+            .into_iter()
+            .filter(|&from| reached & bit(from) != 0)
+        {
+            self.0 |= (reached as u64) << (from as u64 * 6);
+        }
+    }
+
+    pub fn connects(self, from: Side, to: Side) -> bool {
+        self.0 & 1 << (from as u64 * 6 + to as u64) != 0
+    }
+
+    /// Flood fill the space that is not opaque, connecting the faces each region reaches
+    pub fn new(blocks: &ChunkBlocks, registry: &BlockRegistry) -> Self {
+        let index =
+            |at: IVec3| (at.x * CHUNK_WIDTH * CHUNK_WIDTH + at.y * CHUNK_WIDTH + at.z) as usize;
+        let mut seen = vec![false; CHUNK_WIDTH.pow(3) as usize];
+        for (local, block) in blocks.iter() {
+            if registry.is_opaque(block) {
+                seen[index(local)] = true;
+            }
+        }
+        let mut connections = Self::default();
+        let mut stack = Vec::new();
+        for start in cuboid(IVec3::ZERO, IVec3::splat(CHUNK_WIDTH - 1)) {
+            if seen[index(start)] {
+                continue;
+            }
+            seen[index(start)] = true;
+            stack.push(start);
+            let mut reached = 0;
+            while let Some(at) = stack.pop() {
+                for side in Side::ALL {
+                    let next = at + side.dir();
+                    if next.min_element() < 0 || next.max_element() >= CHUNK_WIDTH {
+                        reached |= bit(side);
+                    } else if !seen[index(next)] {
+                        seen[index(next)] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+            connections.connect(reached);
+        }
+        connections
+    }
+}
+
+fn bit(side: Side) -> u8 {
+    1 << side as u8
+}
+
+/// Hide the chunks the camera cannot see through the connected faces of the chunks between
+///
+/// The search spreads from the chunk of the camera, never turning back along an axis.
+pub fn visibility_culling(
+    camera: Single<&Transform, (With<Camera3d>, With<TerrainLoader>)>,
+    index: Res<ChunksIndex>,
+    connections: Query<&FaceConnections>,
+    mut chunks: Query<(&Chunk, &mut Visibility)>,
+) {
+    let (start, _) = global_to_local(camera.translation.floor().as_ivec3());
+    let mut visible = HashSet::from([start]);
+    let mut queue = VecDeque::from([(start, None, 0)]);
+    while let Some((chunk, entered, travelled)) = queue.pop_front() {
+        let Some(&entity) = index.chunks.get(&chunk) else {
+            continue;
+        };
+        let through = connections
+            .get(entity)
+            .copied()
+            .unwrap_or(FaceConnections::ALL);
+        for side in Side::ALL {
+            let back = side.opposite();
+            if travelled & bit(back) != 0
+                || entered.is_some_and(|entered| !through.connects(entered, side))
+            {
+                continue;
+            }
+            let next = chunk + side.dir();
+            if visible.insert(next) {
+                queue.push_back((next, Some(back), travelled | bit(side)));
+            }
+        }
+    }
+    for (&Chunk { chunk }, mut visibility) in &mut chunks {
+        visibility.set_if_neq(if visible.contains(&chunk) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+}